@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use glam::{IVec3, Quat, Vec3};
+
+use crate::{
+    fvd::{RIGHT, UP},
+    spline::TrackSpline,
+};
+
+const ENVELOPE_SEGMENTS: usize = 8; // points sampled around a cylinder envelope
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Ground {
+    Flat(f32), // height of the plane
+    Heightmap {
+        origin: Vec3, // x, z of the first cell, y added to every height
+        cell_size: f32,
+        width: usize,      // cells along x
+        heights: Vec<f32>, // row-major, rows along z
+    },
+}
+
+impl Ground {
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        match self {
+            Ground::Flat(height) => *height,
+            Ground::Heightmap {
+                origin,
+                cell_size,
+                width,
+                heights,
+            } => {
+                if *width == 0 || heights.len() < *width {
+                    return origin.y;
+                }
+                let depth = heights.len() / width;
+                let fx = ((x - origin.x) / cell_size).clamp(0., (width - 1) as f32);
+                let fz = ((z - origin.z) / cell_size).clamp(0., (depth - 1) as f32);
+                let (x0, z0) = (fx.floor() as usize, fz.floor() as usize);
+                let (x1, z1) = ((x0 + 1).min(width - 1), (z0 + 1).min(depth - 1));
+                let (tx, tz) = (fx - x0 as f32, fz - z0 as f32);
+
+                let h = |x: usize, z: usize| heights[z * width + x];
+                let near = h(x0, z0) + (h(x1, z0) - h(x0, z0)) * tx;
+                let far = h(x0, z1) + (h(x1, z1) - h(x0, z1)) * tx;
+                origin.y + near + (far - near) * tz
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Envelope {
+    Cylinder { radius: f32 },        // centred on the heartline
+    Box { width: f32, height: f32 }, // centred on the heartline
+}
+
+impl Envelope {
+    pub fn points(&self, pos: Vec3, rot: Quat) -> Vec<Vec3> {
+        let up = rot * UP;
+        let right = rot * RIGHT;
+        match *self {
+            Envelope::Cylinder { radius } => (0..ENVELOPE_SEGMENTS)
+                .map(|i| {
+                    let angle = i as f32 / ENVELOPE_SEGMENTS as f32 * 2. * PI;
+                    pos + (up * angle.cos() + right * angle.sin()) * radius
+                })
+                .collect(),
+            Envelope::Box { width, height } => [(-1., -1.), (-1., 1.), (1., -1.), (1., 1.)]
+                .iter()
+                .map(|&(x, y): &(f32, f32)| pos + right * x * width / 2. + up * y * height / 2.)
+                .collect(),
+        }
+    }
+
+    pub fn bounding_radius(&self) -> f32 {
+        match *self {
+            Envelope::Cylinder { radius } => radius,
+            Envelope::Box { width, height } => (width * width + height * height).sqrt() / 2.,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CollisionKind {
+    Ground { depth: f32 }, // how far the envelope is below the ground
+    Track { other: usize, distance: f32 }, // closest offending sample elsewhere on the track
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Collision {
+    pub index: usize, // sample index into the spline points
+    pub kind: CollisionKind,
+}
+
+pub fn check(spline: &TrackSpline, ground: &Ground, envelope: &Envelope) -> Vec<Collision> {
+    let mut collisions = check_ground(spline, ground, envelope);
+    collisions.extend(check_self(spline, envelope));
+    collisions.sort_by_key(|c| c.index);
+    collisions
+}
+
+pub fn check_ground(spline: &TrackSpline, ground: &Ground, envelope: &Envelope) -> Vec<Collision> {
+    spline
+        .points()
+        .iter()
+        .enumerate()
+        .filter_map(|(index, &(pos, rot))| {
+            let depth = envelope
+                .points(pos, rot)
+                .iter()
+                .map(|p| ground.height_at(p.x, p.z) - p.y)
+                .fold(f32::NEG_INFINITY, f32::max);
+            (depth > 0.).then_some(Collision {
+                index,
+                kind: CollisionKind::Ground { depth },
+            })
+        })
+        .collect()
+}
+
+pub fn check_self(spline: &TrackSpline, envelope: &Envelope) -> Vec<Collision> {
+    let clearance = envelope.bounding_radius() * 2.;
+    if clearance <= 0. {
+        return Vec::new();
+    }
+    // samples this close along the track are neighbours, not a second pass of the layout
+    let arc_skip = clearance * PI;
+
+    let traveled = spline.distances();
+
+    let cell = |p: Vec3| (p / clearance).floor().as_ivec3();
+    let mut grid: HashMap<_, Vec<usize>> = HashMap::new();
+    for (i, point) in spline.points().iter().enumerate() {
+        grid.entry(cell(point.0)).or_default().push(i);
+    }
+
+    let mut collisions = Vec::new();
+    for (index, point) in spline.points().iter().enumerate() {
+        let centre = cell(point.0);
+        let mut closest: Option<(usize, f32)> = None;
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let Some(others) = grid.get(&(centre + IVec3::new(x, y, z))) else {
+                        continue;
+                    };
+                    for &other in others {
+                        if (traveled[other] - traveled[index]).abs() < arc_skip {
+                            continue;
+                        }
+                        let distance = (spline.points()[other].0 - point.0).length();
+                        if distance < closest.map_or(clearance, |c| c.1) {
+                            closest = Some((other, distance));
+                        }
+                    }
+                }
+            }
+        }
+        if let Some((other, distance)) = closest {
+            collisions.push(Collision {
+                index,
+                kind: CollisionKind::Track { other, distance },
+            });
+        }
+    }
+    collisions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // straight legs through the corners, a point every half metre
+    fn path(corners: &[Vec3]) -> TrackSpline {
+        let mut points = vec![(corners[0], Quat::IDENTITY)];
+        for pair in corners.windows(2) {
+            let steps = (pair[0].distance(pair[1]) / 0.5).round() as usize;
+            for i in 1..=steps {
+                points.push((
+                    pair[0].lerp(pair[1], i as f32 / steps as f32),
+                    Quat::IDENTITY,
+                ));
+            }
+        }
+        TrackSpline::from_points(points)
+    }
+
+    // along z, round a corner and back across along x, gap above the first pass
+    fn crossing(gap: f32) -> TrackSpline {
+        path(&[
+            Vec3::new(0., 0., -20.),
+            Vec3::new(0., 0., 20.),
+            Vec3::new(20., gap, 20.),
+            Vec3::new(20., gap, 0.),
+            Vec3::new(-20., gap, 0.),
+        ])
+    }
+
+    #[test]
+    fn crossing_tracks_collide() {
+        let envelope = Envelope::Cylinder { radius: 1. };
+        assert_eq!(check_self(&crossing(3.), &envelope), []);
+
+        let spline = crossing(1.);
+        let collisions = check_self(&spline, &envelope);
+        assert!(!collisions.is_empty());
+        let points = spline.points();
+        for collision in &collisions {
+            let CollisionKind::Track { other, distance } = collision.kind else {
+                panic!("{:?}", collision);
+            };
+            // each pass only hits the other one, close to where they cross
+            let (a, b) = (points[collision.index].0, points[other].0);
+            assert!(a.y != b.y, "{} and {}", a, b);
+            assert!(a.distance(Vec3::new(0., a.y, 0.)) < 2., "{}", a);
+            assert!((distance - a.distance(b)).abs() < 1e-6);
+        }
+        let closest = collisions
+            .iter()
+            .filter_map(|c| match c.kind {
+                CollisionKind::Track { distance, .. } => Some(distance),
+                _ => None,
+            })
+            .fold(f32::INFINITY, f32::min);
+        assert!((closest - 1.).abs() < 1e-4, "{}", closest);
+
+        // the spatial hash finds the same samples as comparing every pair
+        let clearance = envelope.bounding_radius() * 2.;
+        let distances = spline.distances();
+        let expected = (0..points.len())
+            .filter(|&i| {
+                (0..points.len()).any(|j| {
+                    (distances[i] - distances[j]).abs() >= clearance * PI
+                        && points[i].0.distance(points[j].0) < clearance
+                })
+            })
+            .collect::<Vec<_>>();
+        let found = collisions.iter().map(|c| c.index).collect::<Vec<_>>();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn heightmap_collisions() {
+        // flat at 0, with a 3 m peak in the middle cell
+        let mut heights = vec![0.; 25];
+        heights[12] = 3.;
+        let ground = Ground::Heightmap {
+            origin: Vec3::new(-20., 0., -20.),
+            cell_size: 10.,
+            width: 5,
+            heights,
+        };
+        assert_eq!(ground.height_at(0., 0.), 3.);
+        assert_eq!(ground.height_at(5., 0.), 1.5);
+        assert_eq!(ground.height_at(-100., 100.), 0.); // clamped to the edge
+
+        // a straight run over the peak, the bottom of the envelope 1 m above the track
+        let envelope = Envelope::Box {
+            width: 1.,
+            height: 2.,
+        };
+        let spline = path(&[Vec3::new(0., 2., -20.), Vec3::new(0., 2., 20.)]);
+        assert_eq!(check_ground(&spline, &Ground::Flat(0.), &envelope), []);
+        let collisions = check(&spline, &ground, &envelope);
+        // the corners, half a metre to the side, are under ground within 6.5 m of the peak
+        assert_eq!(collisions.len(), 25);
+        for collision in &collisions {
+            let (pos, _) = spline.points()[collision.index];
+            assert!(pos.z.abs() < 6.5, "{}", pos);
+            let CollisionKind::Ground { depth } = collision.kind else {
+                panic!("{:?}", collision);
+            };
+            let expected = ground.height_at(0.5, pos.z) - 1.;
+            assert!((depth - expected).abs() < 1e-5, "{} != {}", depth, expected);
+        }
+    }
+}
@@ -0,0 +1,655 @@
+use std::{
+    f32::consts::{PI, TAU},
+    fmt,
+    sync::Arc,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const MIN_WIDTH: f32 = 1e-5; // narrowest piece of [0, 1] bounded when parsing
+
+// a formula in t, parsed once and shared between copies of a transition
+#[derive(Clone)]
+pub struct Expression {
+    source: String,
+    root: Arc<Node>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExpressionError {
+    Syntax { position: usize, message: String }, // position is a byte offset into the source
+    NonFinite { t: f32 },
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpressionError::Syntax { position, message } => {
+                write!(f, "{} at character {}", message, position + 1)
+            }
+            ExpressionError::NonFinite { t } => write!(f, "formula is not finite at t = {}", t),
+        }
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(f32),
+    T,
+    Negate(Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Exp,
+    Ln,
+    Sqrt,
+    Abs,
+    Min,
+    Max,
+}
+
+impl Function {
+    const ALL: [(&'static str, Function); 9] = [
+        ("sin", Function::Sin),
+        ("cos", Function::Cos),
+        ("tan", Function::Tan),
+        ("exp", Function::Exp),
+        ("ln", Function::Ln),
+        ("sqrt", Function::Sqrt),
+        ("abs", Function::Abs),
+        ("min", Function::Min),
+        ("max", Function::Max),
+    ];
+
+    fn name(&self) -> &'static str {
+        Function::ALL
+            .iter()
+            .find(|(_, function)| function == self)
+            .map(|(name, _)| *name)
+            .unwrap()
+    }
+
+    fn arity(&self) -> usize {
+        match self {
+            Function::Min | Function::Max => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Self, ExpressionError> {
+        let mut parser = Parser {
+            source,
+            position: 0,
+        };
+        let root = parser.expression()?;
+        parser.skip_whitespace();
+        if parser.position < source.len() {
+            return Err(parser.error("unexpected input"));
+        }
+        root.check(0., 1.)
+            .map_err(|t| ExpressionError::NonFinite { t })?;
+        Ok(Expression {
+            source: source.to_string(),
+            root: Arc::new(root),
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // parsing proves the formula finite on [0, 1], rounding could still overflow near a
+    // steep spot and the solver must never be handed a nan
+    pub fn evaluate(&self, t: f32) -> f32 {
+        let value = self.root.evaluate(t);
+        if value.is_finite() {
+            value
+        } else {
+            0.
+        }
+    }
+
+    // f(from + t * (to - from)) - offset, the part of the curve between from and to
+    // stretched over [0, 1], used when splitting a transition
+    pub fn remap(&self, from: f32, to: f32, offset: f32) -> Expression {
+        let t = Node::Binary(
+            Op::Add,
+            Box::new(Node::Number(from)),
+            Box::new(Node::Binary(
+                Op::Mul,
+                Box::new(Node::T),
+                Box::new(Node::Number(to - from)),
+            )),
+        );
+        let root = Node::Binary(
+            Op::Sub,
+            Box::new(self.root.substitute(&t)),
+            Box::new(Node::Number(offset)),
+        );
+        Expression {
+            source: root.to_string(),
+            root: Arc::new(root),
+        }
+    }
+}
+
+impl fmt::Debug for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Expression({:?})", self.source)
+    }
+}
+
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+// stored as the formula text and parsed again on load
+impl Serialize for Expression {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Expression {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Expression::parse(&source).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Node {
+    fn evaluate(&self, t: f32) -> f32 {
+        match self {
+            Node::Number(value) => *value,
+            Node::T => t,
+            Node::Negate(node) => -node.evaluate(t),
+            Node::Binary(op, a, b) => {
+                let (a, b) = (a.evaluate(t), b.evaluate(t));
+                match op {
+                    Op::Add => a + b,
+                    Op::Sub => a - b,
+                    Op::Mul => a * b,
+                    Op::Div => a / b,
+                    Op::Pow => a.powf(b),
+                }
+            }
+            Node::Call(function, args) => {
+                let x = args[0].evaluate(t);
+                match function {
+                    Function::Sin => x.sin(),
+                    Function::Cos => x.cos(),
+                    Function::Tan => x.tan(),
+                    Function::Exp => x.exp(),
+                    Function::Ln => x.ln(),
+                    Function::Sqrt => x.sqrt(),
+                    Function::Abs => x.abs(),
+                    Function::Min => x.min(args[1].evaluate(t)),
+                    Function::Max => x.max(args[1].evaluate(t)),
+                }
+            }
+        }
+    }
+
+    // Ok when the formula is finite everywhere between low and high, otherwise the t it
+    // could not be bounded around; pieces are halved until the bounds are tight enough
+    fn check(&self, low: f32, high: f32) -> Result<(), f32> {
+        let middle = (low + high) / 2.;
+        match self.bounds(Bounds { low, high }) {
+            Some(_) => Ok(()),
+            None if high - low < MIN_WIDTH => Err(middle),
+            None => self
+                .check(low, middle)
+                .and_then(|_| self.check(middle, high)),
+        }
+    }
+
+    // interval arithmetic, None when the value can be infinite or undefined for some t in
+    // range
+    fn bounds(&self, t: Bounds) -> Option<Bounds> {
+        let bounds = match self {
+            Node::Number(value) => Bounds::point(*value),
+            Node::T => t,
+            Node::Negate(node) => {
+                let x = node.bounds(t)?;
+                Bounds::new(-x.high, -x.low)
+            }
+            Node::Binary(op, a, b) => {
+                let (a, b) = (a.bounds(t)?, b.bounds(t)?);
+                match op {
+                    Op::Add => Bounds::new(a.low + b.low, a.high + b.high),
+                    Op::Sub => Bounds::new(a.low - b.high, a.high - b.low),
+                    Op::Mul => a.corners(b, |x, y| x * y),
+                    Op::Div if b.contains(0.) => return None,
+                    Op::Div => a.corners(b, |x, y| x / y),
+                    Op::Pow => a.pow(b)?,
+                }
+            }
+            Node::Call(function, args) => {
+                let x = args[0].bounds(t)?;
+                match function {
+                    Function::Sin => x.sin(),
+                    Function::Cos => Bounds::new(x.low + PI / 2., x.high + PI / 2.).sin(),
+                    // poles at pi / 2 + k pi
+                    Function::Tan if x.reaches(PI / 2., PI) => return None,
+                    Function::Tan => Bounds::new(x.low.tan(), x.high.tan()),
+                    Function::Exp => Bounds::new(x.low.exp(), x.high.exp()),
+                    Function::Ln if x.low <= 0. => return None,
+                    Function::Ln => Bounds::new(x.low.ln(), x.high.ln()),
+                    Function::Sqrt if x.low < 0. => return None,
+                    Function::Sqrt => Bounds::new(x.low.sqrt(), x.high.sqrt()),
+                    Function::Abs if x.contains(0.) => {
+                        Bounds::new(0., x.low.abs().max(x.high.abs()))
+                    }
+                    Function::Abs => x.corners(x, |x, _| x.abs()),
+                    Function::Min | Function::Max => {
+                        let y = args[1].bounds(t)?;
+                        match function {
+                            Function::Min => Bounds::new(x.low.min(y.low), x.high.min(y.high)),
+                            _ => Bounds::new(x.low.max(y.low), x.high.max(y.high)),
+                        }
+                    }
+                }
+            }
+        };
+        (bounds.low.is_finite() && bounds.high.is_finite()).then_some(bounds)
+    }
+
+    fn substitute(&self, t: &Node) -> Node {
+        match self {
+            Node::T => t.clone(),
+            Node::Number(_) => self.clone(),
+            Node::Negate(node) => Node::Negate(Box::new(node.substitute(t))),
+            Node::Binary(op, a, b) => {
+                Node::Binary(*op, Box::new(a.substitute(t)), Box::new(b.substitute(t)))
+            }
+            Node::Call(function, args) => Node::Call(
+                *function,
+                args.iter().map(|arg| arg.substitute(t)).collect(),
+            ),
+        }
+    }
+}
+
+// the range a value can take, low <= high
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Bounds {
+    low: f32,
+    high: f32,
+}
+
+impl Bounds {
+    fn new(low: f32, high: f32) -> Self {
+        Self { low, high }
+    }
+
+    fn point(value: f32) -> Self {
+        Self::new(value, value)
+    }
+
+    fn contains(&self, value: f32) -> bool {
+        self.low <= value && value <= self.high
+    }
+
+    // whether some at + k * period is in range
+    fn reaches(&self, at: f32, period: f32) -> bool {
+        let k = ((self.low - at) / period).ceil();
+        at + k * period <= self.high
+    }
+
+    // for operations monotonic in each argument, the extremes are at the corners
+    fn corners(&self, other: Bounds, op: impl Fn(f32, f32) -> f32) -> Bounds {
+        let values = [
+            op(self.low, other.low),
+            op(self.low, other.high),
+            op(self.high, other.low),
+            op(self.high, other.high),
+        ];
+        Bounds::new(
+            values.into_iter().fold(f32::INFINITY, f32::min),
+            values.into_iter().fold(f32::NEG_INFINITY, f32::max),
+        )
+    }
+
+    fn sin(&self) -> Bounds {
+        if self.high - self.low >= TAU {
+            return Bounds::new(-1., 1.);
+        }
+        let ends = self.corners(*self, |x, _| x.sin());
+        Bounds::new(
+            if self.reaches(-PI / 2., TAU) {
+                -1.
+            } else {
+                ends.low
+            },
+            if self.reaches(PI / 2., TAU) {
+                1.
+            } else {
+                ends.high
+            },
+        )
+    }
+
+    fn pow(&self, exponent: Bounds) -> Option<Bounds> {
+        if self.low > 0. || (self.low == 0. && exponent.low > 0.) {
+            // monotonic in the base and in the exponent for a positive base
+            return Some(self.corners(exponent, f32::powf));
+        }
+        // a negative base only has a real power for whole exponents
+        let n = exponent.low;
+        if exponent.high != n || n.fract() != 0. {
+            return None;
+        }
+        if n == 0. {
+            return Some(Bounds::point(1.));
+        }
+        if n < 0. && self.contains(0.) {
+            return None;
+        }
+        let ends = self.corners(*self, |x, _| x.powf(n));
+        // even powers turn around at 0
+        Some(if n % 2. == 0. && self.contains(0.) {
+            Bounds::new(0., ends.high)
+        } else {
+            ends
+        })
+    }
+}
+
+// fully parenthesised so the text parses back to the same tree
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Number(value) => write!(f, "{}", value),
+            Node::T => write!(f, "t"),
+            Node::Negate(node) => write!(f, "(-{})", node),
+            Node::Binary(op, a, b) => {
+                let op = match op {
+                    Op::Add => "+",
+                    Op::Sub => "-",
+                    Op::Mul => "*",
+                    Op::Div => "/",
+                    Op::Pow => "^",
+                };
+                write!(f, "({} {} {})", a, op, b)
+            }
+            Node::Call(function, args) => {
+                let args = args.iter().map(Node::to_string).collect::<Vec<_>>();
+                write!(f, "{}({})", function.name(), args.join(", "))
+            }
+        }
+    }
+}
+
+// recursive descent, lowest precedence first: + -, * /, unary -, ^ (right associative)
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn expression(&mut self) -> Result<Node, ExpressionError> {
+        let mut node = self.term()?;
+        loop {
+            let op = if self.eat('+') {
+                Op::Add
+            } else if self.eat('-') {
+                Op::Sub
+            } else {
+                return Ok(node);
+            };
+            node = Node::Binary(op, Box::new(node), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Node, ExpressionError> {
+        let mut node = self.unary()?;
+        loop {
+            let op = if self.eat('*') {
+                Op::Mul
+            } else if self.eat('/') {
+                Op::Div
+            } else {
+                return Ok(node);
+            };
+            node = Node::Binary(op, Box::new(node), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, ExpressionError> {
+        if self.eat('-') {
+            Ok(Node::Negate(Box::new(self.unary()?)))
+        } else {
+            self.power()
+        }
+    }
+
+    fn power(&mut self) -> Result<Node, ExpressionError> {
+        let base = self.atom()?;
+        if self.eat('^') {
+            Ok(Node::Binary(
+                Op::Pow,
+                Box::new(base),
+                Box::new(self.unary()?),
+            ))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn atom(&mut self) -> Result<Node, ExpressionError> {
+        self.skip_whitespace();
+        let rest = &self.source[self.position..];
+        let start = self.position;
+        let Some(c) = rest.chars().next() else {
+            return Err(self.error("unexpected end of formula"));
+        };
+
+        if c == '(' {
+            self.position += 1;
+            let node = self.expression()?;
+            return if self.eat(')') {
+                Ok(node)
+            } else {
+                Err(self.error("expected ')'"))
+            };
+        }
+        if c.is_ascii_digit() || c == '.' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            self.position += len;
+            return rest[..len]
+                .parse()
+                .map(Node::Number)
+                .map_err(|_| ExpressionError::Syntax {
+                    position: start,
+                    message: "invalid number".to_string(),
+                });
+        }
+        if c.is_ascii_alphabetic() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            let name = &rest[..len];
+            self.position += len;
+            return match name {
+                "t" => Ok(Node::T),
+                "pi" => Ok(Node::Number(PI)),
+                "e" => Ok(Node::Number(std::f32::consts::E)),
+                _ => {
+                    let Some(&(_, function)) = Function::ALL.iter().find(|(n, _)| *n == name)
+                    else {
+                        return Err(ExpressionError::Syntax {
+                            position: start,
+                            message: format!("unknown name '{}'", name),
+                        });
+                    };
+                    self.call(function)
+                }
+            };
+        }
+        Err(self.error(&format!("unexpected '{}'", c)))
+    }
+
+    fn call(&mut self, function: Function) -> Result<Node, ExpressionError> {
+        if !self.eat('(') {
+            return Err(self.error(&format!("expected '(' after {}", function.name())));
+        }
+        let mut args = vec![self.expression()?];
+        while self.eat(',') {
+            args.push(self.expression()?);
+        }
+        if !self.eat(')') {
+            return Err(self.error("expected ')'"));
+        }
+        if args.len() != function.arity() {
+            return Err(self.error(&format!(
+                "{} takes {} argument(s)",
+                function.name(),
+                function.arity()
+            )));
+        }
+        Ok(Node::Call(function, args))
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.source[self.position..].starts_with(c) {
+            self.position += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.source[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn error(&self, message: &str) -> ExpressionError {
+        ExpressionError::Syntax {
+            position: self.position,
+            message: message.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(source: &str, t: f32) -> f32 {
+        Expression::parse(source).unwrap().evaluate(t)
+    }
+
+    fn syntax_error(source: &str) -> usize {
+        match Expression::parse(source) {
+            Err(ExpressionError::Syntax { position, .. }) => position,
+            result => panic!("{}: {:?}", source, result),
+        }
+    }
+
+    fn non_finite(source: &str) -> f32 {
+        match Expression::parse(source) {
+            Err(ExpressionError::NonFinite { t }) => t,
+            result => panic!("{}: {:?}", source, result),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(value("1 + 2 * 3", 0.), 7.);
+        assert_eq!(value("(1 + 2) * 3", 0.), 9.);
+        assert_eq!(value("8 / 4 / 2", 0.), 1.);
+        assert_eq!(value("5 - 3 - 1", 0.), 1.);
+        assert_eq!(value("2 * 3 ^ 2", 0.), 18.);
+        assert_eq!(value("2 ^ 3 ^ 2", 0.), 512.); // right associative
+        assert_eq!(value("-t ^ 2", 0.5), -0.25); // ^ binds tighter than unary minus
+        assert_eq!(value("2 ^ -1", 0.), 0.5);
+        assert_eq!(value("--t", 0.5), 0.5);
+        assert_eq!(value("1 - -t * 2", 0.5), 2.);
+        assert_eq!(value("max(t, 1 - t) + min(2, 3)", 0.25), 2.75);
+        assert!((value("sin(pi * t) + e ^ 0", 0.5) - 2.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn syntax_errors_point_at_the_problem() {
+        assert_eq!(syntax_error("1 + * 2"), 4);
+        assert_eq!(syntax_error("foo(t)"), 0);
+        assert_eq!(syntax_error("2 * (t"), 6);
+        assert_eq!(syntax_error("t t"), 2);
+        assert_eq!(syntax_error("1 +"), 3);
+        assert_eq!(syntax_error("1.2.3"), 0);
+        assert_eq!(syntax_error("sin t"), 4);
+        assert!(matches!(
+            Expression::parse("min(t)"),
+            Err(ExpressionError::Syntax { message, .. }) if message.contains("2 argument")
+        ));
+    }
+
+    #[test]
+    fn rejects_formulas_that_are_not_finite_on_the_unit_interval() {
+        assert!(non_finite("1 / t") < 1e-4);
+        // between any grid points, found by bounding rather than sampling
+        assert!((non_finite("1 / (t - 0.505)") - 0.505).abs() < 1e-4);
+        assert!((non_finite("ln(abs(t - 0.3333))") - 0.3333).abs() < 1e-4);
+        assert!((non_finite("tan(3 * t)") - PI / 6.).abs() < 1e-4);
+        assert!(Expression::parse("sqrt(0.5 - t)").is_err());
+        assert!(Expression::parse("(t - 0.5) ^ 0.5").is_err());
+        assert!(Expression::parse("(t - 0.5) ^ -2").is_err());
+
+        for source in [
+            "sqrt(t)",
+            "ln(t + 0.001)",
+            "1 / (t * t - t + 0.3)",
+            "(t - 0.5) ^ 2",
+            "(t - 0.5) ^ 3",
+            "t ^ 0.5",
+            "tan(1.5 * t)",
+            "exp(t) / (2 + cos(40 * t))",
+            "abs(t - 0.5) ^ 1.5",
+        ] {
+            assert!(Expression::parse(source).is_ok(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn remap_and_display_round_trip() {
+        let expression = Expression::parse("t^2 + sin(pi * t) / 4 - -max(t, 0.3)").unwrap();
+        let (from, to, offset) = (0.2, 0.7, 0.5);
+        let remapped = expression.remap(from, to, offset);
+        let parsed = Expression::parse(remapped.source()).unwrap();
+        assert_eq!(parsed, remapped);
+        for i in 0..=10 {
+            let t = i as f32 / 10.;
+            let expected = expression.evaluate(from + t * (to - from)) - offset;
+            assert!((remapped.evaluate(t) - expected).abs() < 1e-5);
+            assert!((parsed.evaluate(t) - expected).abs() < 1e-5);
+        }
+        // the parenthesised text parses back to the same tree
+        let source = expression.root.to_string();
+        assert_eq!(Expression::parse(&source).unwrap().root, expression.root);
+    }
+}
@@ -0,0 +1,511 @@
+use std::fmt;
+
+use crate::{
+    fvd::DT,
+    transitions::{FullTransition, Transition, TransitionFunction, Transitions},
+    units::{DegPerSec, GForce, Seconds},
+};
+
+const REFINE_PASSES: usize = 3;
+
+// shapes tried on every section, the parametric ones that start and end on their levels
+const SHAPES: [TransitionFunction; 8] = [
+    TransitionFunction::Linear,
+    TransitionFunction::Quadratic,
+    TransitionFunction::Cubic,
+    TransitionFunction::Quartic,
+    TransitionFunction::Quintic,
+    TransitionFunction::Sine,
+    TransitionFunction::Smoothstep,
+    TransitionFunction::Smootherstep,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    pub time: f32, // s
+    pub vert: f32, // g
+    pub lat: f32,
+    pub long: f32, // kept for reference, the solver derives longitudinal g from energy
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FitSettings {
+    pub smoothing: f32,   // s, width of the moving average applied before fitting
+    pub min_section: f32, // s
+    pub tolerance: f32,   // g, rms error a section may have before it is split
+}
+
+impl Default for FitSettings {
+    fn default() -> Self {
+        Self {
+            smoothing: 0.2,
+            min_section: 0.3,
+            tolerance: 0.05,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SectionFit {
+    pub start: f32, // s into the recording
+    pub length: f32,
+    pub vert_rms: f32, // g
+    pub lat_rms: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FitReport {
+    pub sections: Vec<SectionFit>,
+    pub vert_rms: f32, // g, over the whole recording against the filtered data
+    pub lat_rms: f32,
+    pub vert_max: f32, // g, largest single error
+    pub lat_max: f32,
+}
+
+#[derive(Debug)]
+pub enum FitError {
+    Parse { line: usize, message: String },
+    NotEnoughData,
+}
+
+impl fmt::Display for FitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FitError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            FitError::NotEnoughData => write!(f, "recording is too short to fit"),
+        }
+    }
+}
+
+impl std::error::Error for FitError {}
+
+// rows of time, vertical, lateral, longitudinal g, a header row is skipped
+pub fn parse_csv(csv: &str) -> Result<Vec<Sample>, FitError> {
+    let mut samples: Vec<Sample> = Vec::new();
+    let mut first_row = true; // the header may follow blank lines
+    for (idx, line) in csv.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let header = std::mem::replace(&mut first_row, false);
+        let error = |message: String| FitError::Parse {
+            line: idx + 1,
+            message,
+        };
+        let fields = line
+            .split(',')
+            .map(|field| field.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>();
+        let fields = match fields {
+            Ok(fields) => fields,
+            Err(_) if header => continue,
+            Err(err) => return Err(error(err.to_string())),
+        };
+        let [time, vert, lat, long] = fields[..] else {
+            return Err(error(format!("expected 4 columns, found {}", fields.len())));
+        };
+        if !fields.iter().all(|field| field.is_finite()) {
+            return Err(error("values must be finite".to_string()));
+        }
+        if samples.last().is_some_and(|last| time <= last.time) {
+            return Err(error("time must increase".to_string()));
+        }
+        samples.push(Sample {
+            time,
+            vert,
+            lat,
+            long,
+        });
+    }
+    Ok(samples)
+}
+
+pub struct Fit {
+    pub transitions: Transitions,
+    pub report: FitReport,
+}
+
+// sections start at the turning points of the filtered forces, since every shape eases
+// from one level to the next, then are split where two shapes fit best and merged back
+// as far as one shape covers, roll is left flat since accelerometers can't tell banking
+// from lateral force
+pub fn fit(samples: &[Sample], settings: &FitSettings) -> Result<Fit, FitError> {
+    let samples = resample(samples)?;
+    let window = (settings.smoothing / DT / 2.).round() as usize;
+    let signals = Signals {
+        vert: smooth(&samples.iter().map(|s| s.vert).collect::<Vec<_>>(), window),
+        lat: smooth(&samples.iter().map(|s| s.lat).collect::<Vec<_>>(), window),
+    };
+    let (vert, lat) = (&signals.vert, &signals.lat);
+    let min_len = ((settings.min_section / DT).round() as usize).max(1);
+    let last = vert.len() - 1;
+
+    let mut breaks = turning_points(vert, settings.tolerance * 2.);
+    breaks.extend(turning_points(lat, settings.tolerance * 2.));
+    breaks.sort();
+    let mut bounds = vec![0];
+    for idx in breaks {
+        if idx >= bounds[bounds.len() - 1] + min_len && idx + min_len <= last {
+            bounds.push(idx);
+        }
+    }
+    bounds.push(last);
+    signals.refine(&mut bounds, min_len);
+
+    let mut split = vec![0];
+    for w in bounds.windows(2) {
+        let mut pending = vec![(w[0], w[1])];
+        while let Some((a, b)) = pending.pop() {
+            if b - a < 2 * min_len || signals.error(a, b) <= settings.tolerance {
+                split.push(b);
+            } else {
+                let at = signals.best_split(a, b, min_len);
+                pending.push((at, b));
+                pending.push((a, at));
+            }
+        }
+    }
+    let mut bounds = split;
+    signals.refine(&mut bounds, min_len);
+
+    // each section reaches as far as one shape still fits, pieces of a ramp only fit once
+    // they are all back together
+    let mut merged = vec![0];
+    let mut k = 0;
+    while k + 1 < bounds.len() {
+        let start = bounds[k];
+        k = (k + 2..bounds.len())
+            .rev()
+            .find(|&j| signals.error(start, bounds[j]) <= settings.tolerance)
+            .unwrap_or(k + 1);
+        merged.push(bounds[k]);
+    }
+    let segments = merged.windows(2).map(|w| (w[0], w[1])).collect::<Vec<_>>();
+
+    let mut transitions = Transitions::new(GForce(vert[0]), GForce(lat[0]), DegPerSec(0.));
+    *transitions.sections_mut() = segments
+        .iter()
+        .map(|&(a, b)| {
+            let axis = |values: &[f32]| {
+                let (shape, _, _) = best_shape(&values[a..=b]);
+                Transition::new(shape, values[b] - values[a])
+            };
+            FullTransition::new(
+                axis(vert),
+                axis(lat),
+                Transition::new(TransitionFunction::Linear, 0.),
+                (b - a) as f32 * DT,
+                None,
+            )
+        })
+        .collect();
+
+    let report = report(&transitions, &segments, vert, lat);
+    Ok(Fit {
+        transitions,
+        report,
+    })
+}
+
+fn report(
+    transitions: &Transitions,
+    segments: &[(usize, usize)],
+    vert: &[f32],
+    lat: &[f32],
+) -> FitReport {
+    // the last sample can land a rounding error past the fitted end
+    let end = transitions.length();
+    let errors = (0..vert.len())
+        .map(|i| {
+            let sample = transitions
+                .interpolate(Seconds((i as f32 * DT).min(end.0)))
+                .expect("time is on the track");
+            (sample.vert.0 - vert[i], sample.lat.0 - lat[i])
+        })
+        .collect::<Vec<_>>();
+    FitReport {
+        sections: segments
+            .iter()
+            .map(|&(a, b)| SectionFit {
+                start: a as f32 * DT,
+                length: (b - a) as f32 * DT,
+                vert_rms: rms(errors[a..=b].iter().map(|e| e.0)),
+                lat_rms: rms(errors[a..=b].iter().map(|e| e.1)),
+            })
+            .collect(),
+        vert_rms: rms(errors.iter().map(|e| e.0)),
+        lat_rms: rms(errors.iter().map(|e| e.1)),
+        vert_max: errors.iter().map(|e| e.0.abs()).fold(0., f32::max),
+        lat_max: errors.iter().map(|e| e.1.abs()).fold(0., f32::max),
+    }
+}
+
+// filtered forces on the solver's time step
+struct Signals {
+    vert: Vec<f32>,
+    lat: Vec<f32>,
+}
+
+impl Signals {
+    fn error(&self, a: usize, b: usize) -> f32 {
+        let (_, vert_rms, _) = best_shape(&self.vert[a..=b]);
+        let (_, lat_rms, _) = best_shape(&self.lat[a..=b]);
+        vert_rms.max(lat_rms)
+    }
+
+    // squared error summed over the samples, comparable between sections of any length
+    fn cost(&self, a: usize, b: usize) -> f32 {
+        let (_, vert_rms, _) = best_shape(&self.vert[a..=b]);
+        let (_, lat_rms, _) = best_shape(&self.lat[a..=b]);
+        (vert_rms * vert_rms + lat_rms * lat_rms) * (b - a + 1) as f32
+    }
+
+    // where two sections fit the data best, at least min_len from either end; the point
+    // furthest from one shape is usually inside a ramp rather than where it starts. Tried
+    // every half min_len, refine then has the reach to find the exact sample
+    fn best_split(&self, a: usize, b: usize, min_len: usize) -> usize {
+        (a + min_len..=b - min_len)
+            .step_by((min_len / 2).max(1))
+            .min_by(|&i, &j| {
+                (self.cost(a, i) + self.cost(i, b)).total_cmp(&(self.cost(a, j) + self.cost(j, b)))
+            })
+            .unwrap_or((a + b) / 2)
+    }
+
+    // moves each inner boundary to where its two sections fit best, small timing errors
+    // cost a lot on steep ramps and smoothing shifts the turning points slightly
+    fn refine(&self, bounds: &mut [usize], min_len: usize) {
+        let reach = min_len / 2;
+        for _ in 0..REFINE_PASSES {
+            for k in 1..bounds.len().saturating_sub(1) {
+                let (a, c) = (bounds[k - 1], bounds[k + 1]);
+                let range = bounds[k].saturating_sub(reach).max(a + min_len)
+                    ..=(bounds[k] + reach).min(c.saturating_sub(min_len));
+                if let Some(best) = range.min_by(|&i, &j| {
+                    (self.cost(a, i) + self.cost(i, c))
+                        .total_cmp(&(self.cost(a, j) + self.cost(j, c)))
+                }) {
+                    bounds[k] = best;
+                }
+            }
+        }
+    }
+}
+
+fn rms(errors: impl Iterator<Item = f32>) -> f32 {
+    let (sum, count) = errors.fold((0., 0), |(sum, count), e| (sum + e * e, count + 1));
+    (sum / count.max(1) as f32).sqrt()
+}
+
+// the shape that best joins the first and last values, with its rms and per sample errors
+fn best_shape(values: &[f32]) -> (TransitionFunction, f32, Vec<f32>) {
+    let (first, last) = (values[0], values[values.len() - 1]);
+    let span = (values.len() - 1).max(1) as f32;
+    SHAPES
+        .into_iter()
+        .map(|shape| {
+            let errors = values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| {
+                    first + shape.interpolate(i as f32 / span) * (last - first) - value
+                })
+                .collect::<Vec<_>>();
+            let rms = (errors.iter().map(|e| e * e).sum::<f32>() / values.len() as f32).sqrt();
+            (shape, rms, errors)
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap()
+}
+
+// extremes that the signal moves away from by more than threshold, ignoring noise
+fn turning_points(values: &[f32], threshold: f32) -> Vec<usize> {
+    let mut points = Vec::new();
+    let (mut high, mut low) = (0, 0);
+    let mut rising = None;
+    for (i, &value) in values.iter().enumerate() {
+        if value > values[high] {
+            high = i;
+        }
+        if value < values[low] {
+            low = i;
+        }
+        match rising {
+            Some(true) | None if values[high] - value > threshold => {
+                if rising.is_some() {
+                    points.push(high);
+                }
+                rising = Some(false);
+                low = i;
+            }
+            Some(false) | None if value - values[low] > threshold => {
+                if rising.is_some() {
+                    points.push(low);
+                }
+                rising = Some(true);
+                high = i;
+            }
+            _ => {}
+        }
+    }
+    points
+}
+
+// onto the solver's time step, so sample indices and section lengths line up with it
+fn resample(samples: &[Sample]) -> Result<Vec<Sample>, FitError> {
+    let (Some(first), Some(last)) = (samples.first(), samples.last()) else {
+        return Err(FitError::NotEnoughData);
+    };
+    let count = ((last.time - first.time) / DT).round() as usize + 1;
+    if count < 2 {
+        return Err(FitError::NotEnoughData);
+    }
+    let mut next = 1;
+    Ok((0..count)
+        .map(|i| {
+            let time = first.time + i as f32 * DT;
+            while next < samples.len() - 1 && samples[next].time < time {
+                next += 1;
+            }
+            let (a, b) = (samples[next - 1], samples[next]);
+            let t = ((time - a.time) / (b.time - a.time)).clamp(0., 1.);
+            let lerp = |x: f32, y: f32| x + (y - x) * t;
+            Sample {
+                time: i as f32 * DT,
+                vert: lerp(a.vert, b.vert),
+                lat: lerp(a.lat, b.lat),
+                long: lerp(a.long, b.long),
+            }
+        })
+        .collect())
+}
+
+// centred moving average, the window shrinks at the ends of the recording
+fn smooth(values: &[f32], half_width: usize) -> Vec<f32> {
+    (0..values.len())
+        .map(|i| {
+            let window =
+                &values[i.saturating_sub(half_width)..(i + half_width + 1).min(values.len())];
+            window.iter().sum::<f32>() / window.len() as f32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a recording of the forces along a track, on the solver's time step
+    fn record(transitions: &Transitions) -> Vec<Sample> {
+        let count = (transitions.length().0 / DT).round() as usize;
+        (0..=count)
+            .map(|i| {
+                let time = (i as f32 * DT).min(transitions.length().0);
+                let sample = transitions.interpolate(Seconds(time)).unwrap();
+                Sample {
+                    time,
+                    vert: sample.vert.0,
+                    lat: sample.lat.0,
+                    long: 0.,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fits_a_known_track() {
+        let axis = |function, change| Transition::new(function, change);
+        let flat = || axis(TransitionFunction::Linear, 0.);
+        let mut track = Transitions::new(GForce(1.), GForce(0.), DegPerSec(0.));
+        *track.sections_mut() = vec![
+            FullTransition::new(
+                axis(TransitionFunction::Cubic, 2.),
+                flat(),
+                flat(),
+                1.5,
+                None,
+            ),
+            FullTransition::new(flat(), flat(), flat(), 1., None),
+            FullTransition::new(
+                axis(TransitionFunction::Sine, -3.5),
+                axis(TransitionFunction::Quintic, 1.),
+                flat(),
+                2.,
+                None,
+            ),
+            FullTransition::new(
+                axis(TransitionFunction::Smoothstep, 2.),
+                axis(TransitionFunction::Cubic, -1.),
+                flat(),
+                1.,
+                None,
+            ),
+        ];
+        let settings = FitSettings {
+            smoothing: 0.,
+            ..FitSettings::default()
+        };
+        let fit = fit(&record(&track), &settings).unwrap();
+
+        assert!((fit.transitions.length().0 - track.length().0).abs() < DT / 2.);
+        assert!(fit.report.vert_max < settings.tolerance);
+        assert!(fit.report.lat_max < settings.tolerance);
+        // the same sections back, to within a step or two
+        assert_eq!(fit.transitions.sections().len(), track.sections().len());
+        for (fitted, section) in fit.transitions.sections().iter().zip(track.sections()) {
+            assert!((fitted.length - section.length).abs() < 2.5 * DT);
+            if section.vert.change != 0. {
+                assert_eq!(fitted.vert.function, section.vert.function);
+            }
+        }
+        for i in 0..=(track.length().0 / DT) as usize {
+            let time = Seconds(i as f32 * DT);
+            let (a, b) = (
+                track.interpolate(time).unwrap(),
+                fit.transitions.interpolate(time).unwrap(),
+            );
+            assert!(
+                (a.vert - b.vert).0.abs() < settings.tolerance * 2.,
+                "{:?}",
+                time
+            );
+            assert!(
+                (a.lat - b.lat).0.abs() < settings.tolerance * 2.,
+                "{:?}",
+                time
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_the_whole_recording() {
+        let samples = [0., 5.5]
+            .map(|time| Sample {
+                time,
+                vert: 1.,
+                lat: 0.,
+                long: 0.,
+            })
+            .to_vec();
+        let fit = fit(&samples, &FitSettings::default()).unwrap();
+        assert!((fit.transitions.length().0 - 5.5).abs() < DT / 2.);
+    }
+
+    #[test]
+    fn parses_csv() {
+        let samples = parse_csv("\n\ntime,vert,lat,long\n0,1,0,0\n\n0.5, 2, 0.1, 0\n").unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[1].vert, 2.);
+
+        let line = |csv: &str| match parse_csv(csv) {
+            Err(FitError::Parse { line, .. }) => line,
+            result => panic!("{:?}", result.map(|s| s.len())),
+        };
+        assert_eq!(line("time,vert,lat,long\n0,1,0,0\n1,NaN,0,0"), 3);
+        assert_eq!(line("0,1,0,0\n1,inf,0,0"), 2);
+        assert_eq!(line("0,1,0,0\n0,1,0,0"), 2);
+        assert_eq!(line("0,1,0\n"), 1);
+        assert_eq!(line("0,1,0,0\nx,1,0,0"), 2);
+    }
+}
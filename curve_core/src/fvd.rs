@@ -1,6 +1,13 @@
+use std::ops::Range;
+
 use glam::{Quat, Vec3};
+use serde::{Deserialize, Serialize};
 
-use crate::{spline::TrackSpline, transitions::Transitions, units::G};
+use crate::{
+    spline::TrackSpline,
+    transitions::Transitions,
+    units::{Meters, MetersPerSec, Seconds},
+};
 pub const DT: f32 = 0.01; // seconds between integrating
 
 pub const GRAVITY: Vec3 = Vec3::new(0., -9.806, 0.); // m/s^2
@@ -11,27 +18,83 @@ pub const RIGHT: Vec3 = Vec3::X;
 
 pub const EPSILON: f32 = 0.0001;
 
-pub fn create_spline(transitions: &Transitions, start: Vec3, start_velocity: f32) -> TrackSpline {
+pub const HEARTLINE_HEIGHT: Meters = Meters(1.1); // above the track centre
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RollAxis {
+    Heartline,
+    TrackCentre,
+}
+
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub heartline: TrackSpline,
+    pub centre: TrackSpline, // heartline offset down along the local up vector
+    pub sections: Vec<usize>, // section active at each point of the splines
+}
+
+// which of the two curves to use, e.g. for an export
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Curve {
+    Heartline,
+    TrackCentre,
+}
+
+impl Curve {
+    pub const ALL: [Curve; 2] = [Curve::Heartline, Curve::TrackCentre];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Curve::Heartline => "Heartline",
+            Curve::TrackCentre => "Track Centre",
+        }
+    }
+}
+
+impl Track {
+    pub fn curve(&self, curve: Curve) -> &TrackSpline {
+        match curve {
+            Curve::Heartline => &self.heartline,
+            Curve::TrackCentre => &self.centre,
+        }
+    }
+
+    // indices of the points simulated while the section was active
+    pub fn section_points(&self, section: usize) -> Range<usize> {
+        let start = self.sections.partition_point(|&s| s < section);
+        let end = self.sections.partition_point(|&s| s <= section);
+        start..end
+    }
+}
+
+pub fn create_spline(
+    transitions: &Transitions,
+    start: Vec3,
+    start_velocity: MetersPerSec,
+    heartline_height: Meters,
+    roll_axis: RollAxis,
+) -> Track {
     let mut spline = TrackSpline::new();
-    let mut velocity = start_velocity;
+    let mut sections = Vec::new();
+    let MetersPerSec(mut velocity) = start_velocity;
+    let Meters(heartline_height) = heartline_height;
     let mut pos = start;
-    let mut traveled = 0.;
     let mut direction = Quat::IDENTITY;
-    let mut time = 0.;
+    let mut time = Seconds(0.);
 
     while time < transitions.length() {
         let length = DT * velocity;
 
         match transitions.interpolate(time) {
-            Some((vert, lat, roll_rate, fixed_speed)) => {
-                velocity = if let Some(v) = fixed_speed {
-                    v
+            Some(sample) => {
+                velocity = if let Some(v) = sample.speed {
+                    v.0
                 } else {
                     velocity
                 };
                 let mut new_dir = direction;
-                let linear_accel =
-                    (vert * G * (direction * -UP)) + (lat * G * (direction * -RIGHT));
+                let linear_accel = (sample.vert.to_accel() * (direction * -UP))
+                    + (sample.lat.to_accel() * (direction * -RIGHT));
                 let remainder_accel = GRAVITY - linear_accel;
                 let forward_accel = remainder_accel.project_onto(direction * FORWARD);
                 let centripetal_accel = remainder_accel - forward_accel;
@@ -46,13 +109,18 @@ pub fn create_spline(transitions: &Transitions, start: Vec3, start_velocity: f32
 
                 let rel_rot = Quat::from_axis_angle(
                     (direction * FORWARD).normalize(),
-                    roll_rate.to_radians() * DT,
+                    sample.roll_rate.to_radians() * DT,
                 );
+                if roll_axis == RollAxis::TrackCentre {
+                    // keep the track centre fixed, so the heartline swings around it
+                    pos += ((rel_rot * new_dir) * UP - new_dir * UP) * heartline_height;
+                }
                 new_dir = rel_rot * new_dir;
 
                 direction = new_dir.normalize();
 
                 pos += direction * FORWARD * length;
+                sections.push(sample.section);
             }
             _ => {
                 break;
@@ -60,10 +128,13 @@ pub fn create_spline(transitions: &Transitions, start: Vec3, start_velocity: f32
         }
         let d_height = (direction * FORWARD * length).y;
         velocity = (velocity * velocity + 2. * GRAVITY.y * d_height).sqrt();
-        time += DT;
-        traveled += length;
-        spline.points.push((pos, direction));
+        time += Seconds(DT);
+        spline.push(pos, direction, time);
     }
 
-    spline
+    Track {
+        centre: spline.offset(Meters(-heartline_height)),
+        heartline: spline,
+        sections,
+    }
 }
@@ -0,0 +1,343 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    templates::Template,
+    transitions::{EditError, FullTransition, Transitions},
+};
+
+pub type GroupId = u32;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Group {
+    pub id: GroupId,
+    pub name: String,
+    pub collapsed: bool,
+    pub scale: f32, // time stretch applied on top of the template
+    pub template: Option<Template>,
+}
+
+// Sections belong to a group through FullTransition::group, so structural edits carry
+// membership along with the sections. A group's sections are always next to each other.
+// Group edits keep downstream levels like other edits.
+impl Transitions {
+    pub fn group(&mut self, range: Range<usize>, name: &str) -> Result<GroupId, EditError> {
+        if range.is_empty() || range.end > self.sections().len() {
+            return Err(EditError::IndexOutOfRange(range.end));
+        }
+        if let Some(id) = self.sections()[range.clone()].iter().find_map(|s| s.group) {
+            return Err(EditError::GroupOverlap(id));
+        }
+        self.prune_groups();
+        let id = self.groups().iter().map(|g| g.id + 1).max().unwrap_or(0);
+        for section in &mut self.sections_mut()[range] {
+            section.group = Some(id);
+        }
+        self.groups_mut().push(Group {
+            id,
+            name: name.to_string(),
+            collapsed: false,
+            scale: 1.,
+            template: None,
+        });
+        Ok(id)
+    }
+
+    pub fn ungroup(&mut self, id: GroupId) -> Result<(), EditError> {
+        self.find_group(id)?;
+        for section in self.sections_mut() {
+            if section.group == Some(id) {
+                section.group = None;
+            }
+        }
+        self.groups_mut().retain(|group| group.id != id);
+        Ok(())
+    }
+
+    // drops groups whose sections have all been removed
+    pub fn prune_groups(&mut self) {
+        let used = self
+            .sections()
+            .iter()
+            .filter_map(|section| section.group)
+            .collect::<Vec<_>>();
+        self.groups_mut().retain(|group| used.contains(&group.id));
+    }
+
+    // the group of a section placed before idx: inside a group it joins that group, and it
+    // stays in its own group when next to it or the only section left in it
+    pub(crate) fn group_at(&self, idx: usize, own: Option<GroupId>) -> Option<GroupId> {
+        let sections = self.sections();
+        let before = idx.checked_sub(1).and_then(|i| sections.get(i)?.group);
+        let after = sections.get(idx).and_then(|section| section.group);
+        let alone = || {
+            self.groups().iter().any(|group| Some(group.id) == own)
+                && !sections.iter().any(|section| section.group == own)
+        };
+        match (before, after) {
+            (Some(before), Some(after)) if before == after => Some(before),
+            _ if own.is_some() && (own == before || own == after || alone()) => own,
+            _ => None,
+        }
+    }
+
+    // sections added or moved by hand are not what the template makes, so regenerating
+    // would throw them away
+    pub(crate) fn detach_template(&mut self, id: Option<GroupId>) {
+        if let Some(group) = id.and_then(|id| self.find_group(id).ok()) {
+            group.template = None;
+        }
+    }
+
+    pub fn group_of(&self, idx: usize) -> Option<&Group> {
+        let id = self.sections().get(idx)?.group?;
+        self.groups().iter().find(|group| group.id == id)
+    }
+
+    pub fn group_range(&self, id: GroupId) -> Option<Range<usize>> {
+        let sections = self.sections();
+        let first = sections.iter().position(|s| s.group == Some(id))?;
+        let last = sections.iter().rposition(|s| s.group == Some(id))?;
+        Some(first..last + 1)
+    }
+
+    pub fn set_collapsed(&mut self, id: GroupId, collapsed: bool) -> Result<(), EditError> {
+        self.find_group(id)?.collapsed = collapsed;
+        Ok(())
+    }
+
+    pub fn scale_group(&mut self, id: GroupId, factor: f32) -> Result<(), EditError> {
+        if factor.is_nan() || factor <= 0. {
+            return Err(EditError::InvalidLength(factor));
+        }
+        let range = self.section_range(id)?;
+        let end = self.start_values(range.end + 1);
+        for section in &mut self.sections_mut()[range.clone()] {
+            scale_section(section, factor);
+        }
+        self.restore_end(range.end, end);
+        self.find_group(id)?.scale *= factor;
+        Ok(())
+    }
+
+    pub fn duplicate_group(&mut self, id: GroupId) -> Result<GroupId, EditError> {
+        let range = self.section_range(id)?;
+        let mut group = self.find_group(id)?.clone();
+        group.id = self.groups().iter().map(|g| g.id + 1).max().unwrap_or(0);
+
+        let copies = self.sections()[range.clone()]
+            .iter()
+            .map(|section| section.clone().with_group(Some(group.id)))
+            .collect::<Vec<_>>();
+        let len = copies.len();
+        let end = self.start_values(range.end + 1);
+        self.sections_mut().splice(range.end..range.end, copies);
+        self.restore_end(range.end + len, end);
+
+        let id = group.id;
+        self.groups_mut().push(group);
+        Ok(id)
+    }
+
+    // to is where the group starts once it has been taken out of the sections
+    pub fn move_group(&mut self, id: GroupId, to: usize) -> Result<(), EditError> {
+        let range = self.section_range(id)?;
+        let len = range.len();
+        if to > self.sections().len() - len {
+            return Err(EditError::IndexOutOfRange(to));
+        }
+        let after = range.start.max(to) + len;
+        let end = self.start_values(after + 1);
+        let mut rest = self.sections().to_vec();
+        let group = rest.drain(range).collect::<Vec<_>>();
+        if let (Some(before), Some(next)) = (to.checked_sub(1).map(|i| &rest[i]), rest.get(to)) {
+            match (before.group, next.group) {
+                (Some(a), Some(b)) if a == b => return Err(EditError::GroupOverlap(a)),
+                _ => {}
+            }
+        }
+        rest.splice(to..to, group);
+        *self.sections_mut() = rest;
+        self.restore_end(after, end);
+        Ok(())
+    }
+
+    pub fn mirror_group(&mut self, id: GroupId) -> Result<(), EditError> {
+        self.mirror(self.section_range(id)?)?;
+        let group = self.find_group(id)?;
+        group.template = group.template.map(|template| template.mirrored());
+        Ok(())
+    }
+
+    // templates have no inverted form, so regenerating undoes this
+    pub fn invert_group(&mut self, id: GroupId) -> Result<(), EditError> {
+        self.invert(self.section_range(id)?)
+    }
+
+    // rebuilds the sections from the group's template, groups without one are left alone
+    pub fn regenerate_group(&mut self, id: GroupId) -> Result<(), EditError> {
+        let range = self.section_range(id)?;
+        let group = self.find_group(id)?.clone();
+        let Some(template) = group.template else {
+            return Ok(());
+        };
+        let mut sections = template.generate(self.start_values(range.start))?;
+        for section in &mut sections {
+            scale_section(section, group.scale);
+            section.group = Some(id);
+        }
+        let len = sections.len();
+        let end = self.start_values(range.end + 1);
+        self.sections_mut().splice(range.clone(), sections);
+        self.restore_end(range.start + len, end);
+        Ok(())
+    }
+
+    pub fn set_group_template(&mut self, id: GroupId, template: Template) -> Result<(), EditError> {
+        let previous = self.find_group(id)?.template.replace(template);
+        let result = self.regenerate_group(id);
+        if result.is_err() {
+            self.find_group(id)?.template = previous;
+        }
+        result
+    }
+
+    fn find_group(&mut self, id: GroupId) -> Result<&mut Group, EditError> {
+        self.groups_mut()
+            .iter_mut()
+            .find(|group| group.id == id)
+            .ok_or(EditError::UnknownGroup(id))
+    }
+
+    fn section_range(&self, id: GroupId) -> Result<Range<usize>, EditError> {
+        self.group_range(id).ok_or(EditError::UnknownGroup(id))
+    }
+}
+
+fn scale_section(section: &mut FullTransition, factor: f32) {
+    section.length *= factor;
+    for transition in [&mut section.vert, &mut section.lat, &mut section.roll] {
+        transition.offset *= factor;
+        transition.duration = transition.duration.map(|d| d * factor);
+    }
+    // roll is a rate, so slow it down to keep the angles the element rolls through
+    section.roll.change /= factor;
+    section.bindings.length = None;
+    section.bindings.roll = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        templates::Template,
+        units::{DegPerSec, GForce},
+    };
+
+    fn layout(count: usize) -> Transitions {
+        let mut transitions = Transitions::new(GForce(1.), GForce(0.), DegPerSec(0.));
+        *transitions.sections_mut() = (0..count)
+            .map(|i| FullTransition {
+                length: 1. + i as f32,
+                ..FullTransition::default()
+            })
+            .collect();
+        transitions
+    }
+
+    fn groups(transitions: &Transitions) -> Vec<Option<GroupId>> {
+        transitions.sections().iter().map(|s| s.group).collect()
+    }
+
+    #[test]
+    fn overlapping_groups_are_rejected() {
+        let mut transitions = layout(4);
+        let a = transitions.group(0..3, "a").unwrap();
+        assert_eq!(
+            transitions.group(1..2, "b"),
+            Err(EditError::GroupOverlap(a))
+        );
+        assert_eq!(
+            transitions.group(2..4, "b"),
+            Err(EditError::GroupOverlap(a))
+        );
+        let b = transitions.group(3..4, "b").unwrap();
+        transitions.scale_group(a, 2.).unwrap();
+        let lengths = transitions
+            .sections()
+            .iter()
+            .map(|s| s.length)
+            .collect::<Vec<_>>();
+        assert_eq!(lengths, [2., 4., 6., 4.]);
+        assert_eq!(transitions.group_range(b), Some(3..4));
+
+        // merging b's only section into a leaves no b behind
+        transitions.merge(2).unwrap();
+        assert_eq!(groups(&transitions), [Some(a); 3]);
+        assert_eq!(transitions.groups().len(), 1);
+        assert_eq!(transitions.groups()[0].id, a);
+    }
+
+    #[test]
+    fn inserted_sections_keep_groups_together() {
+        let mut transitions = layout(4);
+        let a = transitions.group(1..3, "a").unwrap();
+
+        // inside a group joins it, elsewhere a copy of a member does not
+        transitions.insert(2, FullTransition::default()).unwrap();
+        let member = transitions.sections()[1].clone();
+        transitions.insert(0, member.clone()).unwrap();
+        transitions.insert(6, member).unwrap();
+        assert_eq!(
+            groups(&transitions),
+            [None, None, Some(a), Some(a), Some(a), None, None]
+        );
+
+        // moving a section into the group joins it, out of the group leaves it
+        transitions.move_section(0, 3).unwrap();
+        assert_eq!(transitions.group_range(a), Some(1..5));
+        transitions.move_section(1, 6).unwrap();
+        assert_eq!(transitions.group_range(a), Some(1..4));
+        assert_eq!(transitions.sections()[6].group, None);
+
+        // removing every member removes the group
+        for _ in 0..3 {
+            transitions.remove(1).unwrap();
+        }
+        assert_eq!(groups(&transitions), [None; 4]);
+        assert!(transitions.groups().is_empty());
+    }
+
+    #[test]
+    fn groups_cannot_move_into_each_other() {
+        let mut transitions = layout(5);
+        let a = transitions.group(0..1, "a").unwrap();
+        let b = transitions.group(1..4, "b").unwrap();
+        assert_eq!(
+            transitions.move_group(a, 1),
+            Err(EditError::GroupOverlap(b))
+        );
+        assert_eq!(
+            transitions.insert_template(2, &Template::EXAMPLES[0]),
+            Err(EditError::GroupOverlap(b))
+        );
+        assert_eq!(transitions.sections().len(), 5);
+        transitions.move_group(a, 3).unwrap();
+        assert_eq!(transitions.group_range(b), Some(0..3));
+        assert_eq!(transitions.group_range(a), Some(3..4));
+    }
+
+    #[test]
+    fn editing_a_template_group_by_hand_detaches_the_template() {
+        let mut transitions = layout(2);
+        let id = transitions
+            .insert_template(1, &Template::EXAMPLES[0])
+            .unwrap();
+        transitions.insert(2, FullTransition::default()).unwrap();
+        assert_eq!(transitions.group_range(id), Some(1..5));
+        let before = transitions.clone();
+        transitions.regenerate_group(id).unwrap();
+        assert_eq!(transitions.sections(), before.sections());
+    }
+}
@@ -0,0 +1,405 @@
+use crate::{
+    project::{Project, StartState},
+    transitions::{ForceLevels, FullTransition, Transitions},
+};
+
+#[derive(Clone, Debug)]
+pub enum Command {
+    SetSection {
+        idx: usize,
+        before: Box<FullTransition>,
+        after: Box<FullTransition>,
+    },
+    SetTransitions {
+        before: Box<Transitions>, // sections and groups, for structural edits
+        after: Box<Transitions>,
+    },
+    SetForceStart {
+        before: ForceLevels,
+        after: ForceLevels,
+    },
+    SetStart {
+        before: StartState,
+        after: StartState,
+    },
+    SetParameter {
+        name: String,
+        before: Option<f32>, // None when the parameter is not defined
+        after: Option<f32>,
+    },
+}
+
+impl Command {
+    pub fn apply(&self, project: &mut Project) {
+        self.set(project, false);
+    }
+
+    pub fn revert(&self, project: &mut Project) {
+        self.set(project, true);
+    }
+
+    fn set(&self, project: &mut Project, reverse: bool) {
+        match self {
+            Command::SetSection { idx, before, after } => {
+                project.transitions.sections_mut()[*idx] = if reverse {
+                    (**before).clone()
+                } else {
+                    (**after).clone()
+                };
+            }
+            Command::SetTransitions { before, after } => {
+                project.transitions = if reverse {
+                    (**before).clone()
+                } else {
+                    (**after).clone()
+                };
+            }
+            Command::SetForceStart { before, after } => {
+                project
+                    .transitions
+                    .set_start(if reverse { *before } else { *after });
+            }
+            Command::SetStart { before, after } => {
+                project.start = if reverse { *before } else { *after };
+            }
+            Command::SetParameter {
+                name,
+                before,
+                after,
+            } => project.set_parameter(name, if reverse { *before } else { *after }),
+        }
+    }
+
+    // folds a later edit of the same thing into this one, used while grouping drags
+    fn absorb(&mut self, next: &Command) -> bool {
+        match (self, next) {
+            (
+                Command::SetSection { idx, after, .. },
+                Command::SetSection {
+                    idx: next_idx,
+                    after: next_after,
+                    ..
+                },
+            ) if idx == next_idx => after.clone_from(next_after),
+            (
+                Command::SetTransitions { after, .. },
+                Command::SetTransitions {
+                    after: next_after, ..
+                },
+            ) => after.clone_from(next_after),
+            (
+                Command::SetForceStart { after, .. },
+                Command::SetForceStart {
+                    after: next_after, ..
+                },
+            ) => *after = *next_after,
+            (
+                Command::SetStart { after, .. },
+                Command::SetStart {
+                    after: next_after, ..
+                },
+            ) => *after = *next_after,
+            (
+                Command::SetParameter { name, after, .. },
+                Command::SetParameter {
+                    name: next_name,
+                    after: next_after,
+                    ..
+                },
+            ) if name == next_name => *after = *next_after,
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    undo: Vec<Vec<Command>>, // each step is a group of commands applied in order
+    redo: Vec<Vec<Command>>,
+    group: Option<Vec<Command>>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn execute(&mut self, project: &mut Project, command: Command) {
+        command.apply(project);
+        self.record(command);
+    }
+
+    // records a command that has already been applied to the project
+    pub fn record(&mut self, command: Command) {
+        self.redo.clear();
+        match &mut self.group {
+            Some(group) => {
+                if !group.last_mut().is_some_and(|last| last.absorb(&command)) {
+                    group.push(command);
+                }
+            }
+            None => self.undo.push(vec![command]),
+        }
+    }
+
+    pub fn edit<R, E>(
+        &mut self,
+        project: &mut Project,
+        edit: impl FnOnce(&mut Transitions) -> Result<R, E>,
+    ) -> Result<R, E> {
+        let before = project.transitions.clone();
+        let result = edit(&mut project.transitions)?;
+        if project.transitions.sections() != before.sections()
+            || project.transitions.groups() != before.groups()
+        {
+            let after = Box::new(project.transitions.clone());
+            self.record(Command::SetTransitions {
+                before: Box::new(before),
+                after,
+            });
+        }
+        Ok(result)
+    }
+
+    // records section idx, already edited in place from before; a bound value that was
+    // edited edits its parameter and every section using it, all as one undo step, so undo
+    // never leaves a section disagreeing with its parameter
+    pub fn edit_section(&mut self, project: &mut Project, idx: usize, before: FullTransition) {
+        let mut step = Vec::new();
+        for (name, after) in before.bound_edits(&project.transitions.sections()[idx]) {
+            let previous = project.parameters.get(&name).copied();
+            if previous.is_some() && previous != Some(after) {
+                let command = Command::SetParameter {
+                    name,
+                    before: previous,
+                    after: Some(after),
+                };
+                command.apply(project);
+                step.push(command);
+            }
+        }
+        // a newly chosen binding takes its parameter's value
+        project.transitions.apply_parameters(&project.parameters);
+        let after = &project.transitions.sections()[idx];
+        if *after != before {
+            step.push(Command::SetSection {
+                idx,
+                before: Box::new(before),
+                after: Box::new(after.clone()),
+            });
+        }
+        self.record_step(step);
+    }
+
+    // several commands undone together, or added to the open group one by one
+    fn record_step(&mut self, step: Vec<Command>) {
+        if step.is_empty() {
+            return;
+        }
+        if self.group.is_some() {
+            for command in step {
+                self.record(command);
+            }
+        } else {
+            self.redo.clear();
+            self.undo.push(step);
+        }
+    }
+
+    pub fn begin_group(&mut self) {
+        if self.group.is_none() {
+            self.group = Some(Vec::new());
+        }
+    }
+
+    pub fn end_group(&mut self) {
+        if let Some(group) = self.group.take() {
+            if !group.is_empty() {
+                self.undo.push(group);
+            }
+        }
+    }
+
+    pub fn undo(&mut self, project: &mut Project) -> bool {
+        self.end_group();
+        let Some(step) = self.undo.pop() else {
+            return false;
+        };
+        for command in step.iter().rev() {
+            command.revert(project);
+        }
+        self.redo.push(step);
+        true
+    }
+
+    pub fn redo(&mut self, project: &mut Project) -> bool {
+        self.end_group();
+        let Some(step) = self.redo.pop() else {
+            return false;
+        };
+        for command in &step {
+            command.apply(project);
+        }
+        self.undo.push(step);
+        true
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty() || self.group.as_ref().is_some_and(|g| !g.is_empty())
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.group = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::{DegPerSec, GForce};
+
+    // one section whose vert change is bound to hill_g = 1
+    fn bound_project() -> Project {
+        let mut project = Project::new(Transitions::new(GForce(1.), GForce(0.), DegPerSec(0.)));
+        project.transitions.sections_mut()[0].bindings.vert = Some("hill_g".to_string());
+        project.set_parameter("hill_g", Some(1.));
+        project
+    }
+
+    fn vert(project: &Project) -> f32 {
+        project.transitions.sections()[0].vert.change
+    }
+
+    // what the editor does every frame, with the edit applied to the section in place
+    fn frame(history: &mut History, project: &mut Project, edit: impl FnOnce(&mut FullTransition)) {
+        let before = project.transitions.sections()[0].clone();
+        edit(&mut project.transitions.sections_mut()[0]);
+        history.edit_section(project, 0, before);
+    }
+
+    #[test]
+    fn bound_edits_undo_as_one_step() {
+        let mut project = bound_project();
+        let mut history = History::new();
+        frame(&mut history, &mut project, |section| {
+            section.vert.change = 2.
+        });
+        assert_eq!(project.parameters["hill_g"], 2.);
+        assert_eq!(vert(&project), 2.);
+
+        assert!(history.undo(&mut project));
+        // idle frames after the undo record nothing
+        frame(&mut history, &mut project, |_| ());
+        frame(&mut history, &mut project, |_| ());
+        assert_eq!(project.parameters["hill_g"], 1.);
+        assert_eq!(vert(&project), 1.);
+        assert!(!history.can_undo());
+        assert!(history.can_redo());
+
+        assert!(history.redo(&mut project));
+        assert_eq!(project.parameters["hill_g"], 2.);
+        assert_eq!(vert(&project), 2.);
+    }
+
+    #[test]
+    fn binding_a_value_takes_the_parameter() {
+        let mut project = bound_project();
+        project.transitions.sections_mut()[0].bindings.vert = None;
+        project.transitions.sections_mut()[0].vert.change = 0.5;
+        let mut history = History::new();
+        frame(&mut history, &mut project, |section| {
+            section.bindings.vert = Some("hill_g".to_string())
+        });
+        assert_eq!(vert(&project), 1.);
+        history.undo(&mut project);
+        frame(&mut history, &mut project, |_| ());
+        assert_eq!(vert(&project), 0.5);
+        assert_eq!(project.transitions.sections()[0].bindings.vert, None);
+        assert!(history.can_redo());
+    }
+
+    fn set_speed(history: &mut History, project: &mut Project, velocity: f32) {
+        let before = project.start;
+        history.execute(
+            project,
+            Command::SetStart {
+                before,
+                after: StartState { velocity, ..before },
+            },
+        );
+    }
+
+    #[test]
+    fn undo_and_redo_in_order() {
+        let mut project = bound_project();
+        let mut history = History::new();
+        for velocity in [6., 7., 8.] {
+            set_speed(&mut history, &mut project, velocity);
+        }
+        for expected in [7., 6., 5.] {
+            assert!(history.undo(&mut project));
+            assert_eq!(project.start.velocity, expected);
+        }
+        assert!(!history.undo(&mut project));
+        for expected in [6., 7., 8.] {
+            assert!(history.redo(&mut project));
+            assert_eq!(project.start.velocity, expected);
+        }
+        assert!(!history.redo(&mut project));
+    }
+
+    #[test]
+    fn recording_clears_redo() {
+        let mut project = bound_project();
+        let mut history = History::new();
+        set_speed(&mut history, &mut project, 6.);
+        set_speed(&mut history, &mut project, 7.);
+        history.undo(&mut project);
+        assert!(history.can_redo());
+        set_speed(&mut history, &mut project, 9.);
+        assert!(!history.can_redo());
+        assert!(!history.redo(&mut project));
+        history.undo(&mut project);
+        assert_eq!(project.start.velocity, 6.);
+    }
+
+    #[test]
+    fn a_drag_undoes_as_one_step() {
+        let mut project = bound_project();
+        let mut history = History::new();
+        set_speed(&mut history, &mut project, 6.);
+
+        history.begin_group();
+        for change in [1.5, 2., 2.5] {
+            frame(&mut history, &mut project, |section| {
+                section.bindings.vert = None;
+                section.vert.change = change;
+            });
+        }
+        set_speed(&mut history, &mut project, 25.);
+        assert!(history.can_undo());
+        history.end_group();
+        // the drag's edits of the same section are folded into one command
+        assert_eq!(history.undo.len(), 2);
+        assert_eq!(history.undo[1].len(), 2);
+
+        assert!(history.undo(&mut project));
+        assert_eq!(vert(&project), 1.);
+        assert_eq!(project.start.velocity, 6.);
+        assert!(history.redo(&mut project));
+        assert_eq!(vert(&project), 2.5);
+        assert_eq!(project.start.velocity, 25.);
+
+        // a group with nothing in it adds no step
+        history.begin_group();
+        history.end_group();
+        assert_eq!(history.undo.len(), 2);
+    }
+}
@@ -0,0 +1,461 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    keyframes::{Keyframe, Keyframes},
+    transitions::{
+        Easing, FullTransition, SectionInfo, Transition, TransitionFunction, Transitions,
+    },
+    units::Seconds,
+    validate::Field,
+};
+
+const STEP: f32 = 0.01; // s between the samples used for the derivatives at a join
+
+type Derivatives = (f32, f32); // slope, curvature
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JoinSettings {
+    pub force_slope: f32, // g/s allowed between the two sides of a join
+    pub roll_slope: f32,  // deg/s per s
+    pub curvature: bool,  // also look for jumps in the second derivative
+    pub force_curvature: f32,
+    pub roll_curvature: f32,
+    pub blend_length: f32, // s, taken from the sections either side of a blend
+}
+
+impl Default for JoinSettings {
+    fn default() -> Self {
+        Self {
+            force_slope: 0.5,
+            roll_slope: 50.,
+            curvature: false,
+            force_curvature: 20.,
+            roll_curvature: 2000.,
+            blend_length: 0.3,
+        }
+    }
+}
+
+impl JoinSettings {
+    fn tolerance(&self, field: Field, order: Order) -> f32 {
+        match (field, order) {
+            (Field::Roll, Order::Slope) => self.roll_slope,
+            (Field::Roll, Order::Curvature) => self.roll_curvature,
+            (_, Order::Slope) => self.force_slope,
+            (_, Order::Curvature) => self.force_curvature,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    Slope,     // first derivative
+    Curvature, // second derivative
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Discontinuity {
+    pub join: usize, // index of the section after the join, shown numbered from 1
+    pub field: Field,
+    pub order: Order,
+    pub before: f32, // per s (or s²) on each side of the join
+    pub after: f32,
+}
+
+impl fmt::Display for Discontinuity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, unit) = match self.order {
+            Order::Slope => ("slope", "s"),
+            Order::Curvature => ("curvature", "s²"),
+        };
+        write!(
+            f,
+            "join {}/{} {}: {} jumps from {:.2} to {:.2} per {}",
+            self.join,
+            self.join + 1,
+            self.field.name(),
+            name,
+            self.before,
+            self.after,
+            unit
+        )
+    }
+}
+
+impl Transitions {
+    pub fn discontinuities(&self, settings: &JoinSettings) -> Vec<Discontinuity> {
+        let mut found = Vec::new();
+        for join in 1..self.sections().len() {
+            for (field, before, after) in self.join_derivatives(join) {
+                let mut orders = vec![(Order::Slope, before.0, after.0)];
+                if settings.curvature {
+                    orders.push((Order::Curvature, before.1, after.1));
+                }
+                for (order, before, after) in orders {
+                    if (after - before).abs() > settings.tolerance(field, order) {
+                        found.push(Discontinuity {
+                            join,
+                            field,
+                            order,
+                            before,
+                            after,
+                        });
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    // makes every join C1, first by fitting the end tangents of bezier and keyframe curves
+    // (or easing a plateau's linear ramp), otherwise by replacing the time around the join
+    // with a blend section, returns the number of joins changed
+    pub fn smooth_joins(&mut self, settings: &JoinSettings) -> usize {
+        let mut smoothed = 0;
+        // backwards, so blends inserted at a join leave the earlier indices alone
+        for join in (1..self.sections().len()).rev() {
+            let mut blend = (false, false); // cut into the section before, after the join
+            let mut changed = false;
+            for (field, before, after) in self.join_derivatives(join) {
+                let tolerance = settings.tolerance(field, Order::Slope);
+                let (before, after) = (before.0, after.0);
+                if (after - before).abs() <= tolerance {
+                    continue;
+                }
+                if self.fit_slope(join, field, true, before, tolerance)
+                    || self.fit_slope(join - 1, field, false, after, tolerance)
+                {
+                    changed = true;
+                    continue;
+                }
+                // a flat side is left whole, unless neither side is steep on its own
+                let steep = (before.abs() > tolerance, after.abs() > tolerance);
+                blend.0 |= steep.0 || !steep.1;
+                blend.1 |= steep.1 || !steep.0;
+            }
+            if (blend.0 || blend.1) && self.blend(join, blend, settings.blend_length) {
+                changed = true;
+            }
+            if changed {
+                smoothed += 1;
+            }
+        }
+        smoothed
+    }
+
+    // the derivatives before and after a join on each axis
+    fn join_derivatives(&self, join: usize) -> [(Field, Derivatives, Derivatives); 3] {
+        let (a, b) = (&self.sections()[join - 1], &self.sections()[join]);
+        let (a_start, b_start) = (self.start_values(join - 1), self.start_values(join));
+        [
+            (
+                Field::Vert,
+                &a.vert,
+                &b.vert,
+                a_start.vert.0,
+                b_start.vert.0,
+            ),
+            (Field::Lat, &a.lat, &b.lat, a_start.lat.0, b_start.lat.0),
+            (
+                Field::Roll,
+                &a.roll,
+                &b.roll,
+                a_start.roll_rate.0,
+                b_start.roll_rate.0,
+            ),
+        ]
+        .map(|(field, before, after, a_start, b_start)| {
+            (
+                field,
+                derivatives(&before.resolve(a_start), a.length, true),
+                derivatives(&after.resolve(b_start), b.length, false),
+            )
+        })
+    }
+
+    // changes one end of a transition so it leaves or arrives at the given slope,
+    // without moving its end value
+    fn fit_slope(
+        &mut self,
+        idx: usize,
+        field: Field,
+        at_start: bool,
+        slope: f32,
+        tolerance: f32,
+    ) -> bool {
+        let incoming = self.start_values(idx);
+        let section = &self.sections()[idx];
+        let (transition, incoming) = match field {
+            Field::Vert => (&section.vert, incoming.vert.0),
+            Field::Lat => (&section.lat, incoming.lat.0),
+            _ => (&section.roll, incoming.roll_rate.0),
+        };
+        let (start, duration) = transition.window(section.length);
+        let touches = if at_start {
+            start == 0.
+        } else {
+            start + duration >= section.length
+        };
+        let change = transition.resolve(incoming).change;
+        if !touches || duration <= 0. || change == 0. {
+            return false;
+        }
+        // the slope in t of the normalised function
+        let target = slope * duration / change;
+        let flat = slope.abs() <= tolerance;
+
+        let function = match &transition.function {
+            TransitionFunction::Bezier { x1, y1, x2, y2 } => {
+                let (x1, x2) = (x1.clamp(0., 1.), x2.clamp(0., 1.));
+                match at_start {
+                    true if x1 > 0. => TransitionFunction::Bezier {
+                        x1,
+                        y1: target * x1,
+                        x2,
+                        y2: *y2,
+                    },
+                    false if x2 < 1. => TransitionFunction::Bezier {
+                        x1,
+                        y1: *y1,
+                        x2,
+                        y2: 1. - target * (1. - x2),
+                    },
+                    _ => return false,
+                }
+            }
+            TransitionFunction::Keyframes(keyframes) => {
+                let mut keyframes = keyframes.clone();
+                let idx = if at_start {
+                    0
+                } else {
+                    keyframes.keys().len() - 1
+                };
+                let key = keyframes.keys()[idx];
+                keyframes.set(
+                    idx,
+                    Keyframe {
+                        slope: target,
+                        ..key
+                    },
+                );
+                TransitionFunction::Keyframes(keyframes)
+            }
+            // a plateau can only be eased flat, which is enough when the other side is flat
+            TransitionFunction::Plateau {
+                ramp_in,
+                ramp_out,
+                ease_in,
+                ease_out,
+            } if flat => {
+                let eased = |easing| match easing {
+                    Easing::Linear => Easing::Sine,
+                    easing => easing,
+                };
+                TransitionFunction::Plateau {
+                    ramp_in: *ramp_in,
+                    ramp_out: *ramp_out,
+                    ease_in: if at_start { eased(*ease_in) } else { *ease_in },
+                    ease_out: if at_start {
+                        *ease_out
+                    } else {
+                        eased(*ease_out)
+                    },
+                }
+            }
+            _ => return false,
+        };
+        if function == transition.function {
+            return false;
+        }
+
+        let section = &mut self.sections_mut()[idx];
+        let transition = match field {
+            Field::Vert => &mut section.vert,
+            Field::Lat => &mut section.lat,
+            _ => &mut section.roll,
+        };
+        transition.function = function;
+        true
+    }
+
+    // replaces the time either side of the join with one section whose curves are cubic
+    // hermite blends matching the levels and slopes at both of its ends, false when neither
+    // section could be cut
+    fn blend(&mut self, join: usize, (cut_before, cut_after): (bool, bool), length: f32) -> bool {
+        let share = if cut_before && cut_after {
+            length / 2.
+        } else {
+            length
+        };
+        // after both splits the sections being replaced start at the join
+        let mut count = 0;
+        if cut_after {
+            let after = self.sections()[join].length;
+            if self.split(join, Seconds(share.min(after / 2.))).is_ok() {
+                count += 1;
+            }
+        }
+        if cut_before {
+            let before = self.sections()[join - 1].length;
+            if self
+                .split(join - 1, Seconds(before - share.min(before / 2.)))
+                .is_ok()
+            {
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return false;
+        }
+        let region = join..join + count;
+
+        let start = self.start_values(region.start);
+        let end = self.start_values(region.end);
+        let length = self.sections()[region.clone()]
+            .iter()
+            .map(|section| section.length)
+            .sum::<f32>();
+        let slopes = |transitions: &Transitions, idx: usize, at_end: bool| {
+            let section = &transitions.sections()[idx];
+            let incoming = transitions.start_values(idx);
+            (
+                derivatives(
+                    &section.vert.resolve(incoming.vert.0),
+                    section.length,
+                    at_end,
+                )
+                .0,
+                derivatives(&section.lat.resolve(incoming.lat.0), section.length, at_end).0,
+                derivatives(
+                    &section.roll.resolve(incoming.roll_rate.0),
+                    section.length,
+                    at_end,
+                )
+                .0,
+            )
+        };
+        let slope_in = slopes(self, region.start - 1, true);
+        let slope_out = match self.sections().get(region.end) {
+            Some(_) => slopes(self, region.end, false),
+            None => (0., 0., 0.),
+        };
+
+        let speed = self.sections()[region.start].speed;
+        let group = match (
+            self.sections()[region.start - 1].group,
+            self.sections().get(region.end).and_then(|s| s.group),
+        ) {
+            (Some(a), Some(b)) if a == b => Some(a),
+            _ => None,
+        };
+        let blended = FullTransition::new(
+            hermite((end.vert - start.vert).0, slope_in.0, slope_out.0, length),
+            hermite((end.lat - start.lat).0, slope_in.1, slope_out.1, length),
+            hermite(
+                (end.roll_rate - start.roll_rate).0,
+                slope_in.2,
+                slope_out.2,
+                length,
+            ),
+            length,
+            speed,
+        )
+        .with_info(SectionInfo {
+            name: Some("Blend".to_string()),
+            ..SectionInfo::default()
+        })
+        .with_group(group);
+        self.sections_mut().splice(region.clone(), [blended]);
+        self.restore_end(region.start, end);
+        true
+    }
+}
+
+// from 0 to delta over length with the given slopes (per s) at the ends
+fn hermite(delta: f32, slope_in: f32, slope_out: f32, length: f32) -> Transition {
+    // keyframe values are scaled by the change, so a flat blend uses a unit change
+    let change = if delta.abs() > 1e-4 { delta } else { 1. };
+    let keys = vec![
+        Keyframe::new(0., 0., slope_in * length / change),
+        Keyframe::new(1., delta / change, slope_out * length / change),
+    ];
+    Transition::new(TransitionFunction::Keyframes(Keyframes::new(keys)), change)
+}
+
+// one-sided derivatives of a resolved transition at the start or end of its section
+fn derivatives(transition: &Transition, length: f32, at_end: bool) -> Derivatives {
+    let h = STEP.min(length / 4.);
+    if h <= 0. {
+        return (0., 0.);
+    }
+    let (origin, direction) = if at_end { (length, -1.) } else { (0., 1.) };
+    let [f0, f1, f2] = [0., 1., 2.].map(|i| transition.sample(origin + direction * i * h, length));
+    (
+        direction * (-3. * f0 + 4. * f1 - f2) / (2. * h),
+        (f0 - 2. * f1 + f2) / (h * h),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::{DegPerSec, GForce};
+
+    // sections whose vert ramps linearly by each change over 1 s
+    fn ramps(changes: &[f32]) -> Transitions {
+        let mut transitions = Transitions::new(GForce(1.), GForce(0.), DegPerSec(0.));
+        let flat = || Transition::new(TransitionFunction::Linear, 0.);
+        *transitions.sections_mut() = changes
+            .iter()
+            .map(|&change| {
+                FullTransition::new(
+                    Transition::new(TransitionFunction::Linear, change),
+                    flat(),
+                    flat(),
+                    1.,
+                    None,
+                )
+            })
+            .collect();
+        transitions
+    }
+
+    fn smooths(changes: &[f32]) {
+        let settings = JoinSettings::default();
+        let mut transitions = ramps(changes);
+        assert_eq!(transitions.discontinuities(&settings).len(), 1);
+        let end = transitions.start_values(changes.len());
+        assert_eq!(transitions.smooth_joins(&settings), 1);
+        assert_eq!(transitions.discontinuities(&settings), []);
+        assert!(
+            (transitions.start_values(transitions.sections().len()).vert - end.vert)
+                .0
+                .abs()
+                < 1e-4
+        );
+    }
+
+    #[test]
+    fn kinks_below_the_tolerance_on_each_side_are_blended() {
+        // each slope is under the 0.5 g/s tolerance, the jump between them is not
+        smooths(&[0.4, -0.4]);
+    }
+
+    #[test]
+    fn large_kinks_are_blended() {
+        smooths(&[2., -2.]);
+        smooths(&[0., 2.]);
+    }
+
+    #[test]
+    fn smooth_joins_are_left_alone() {
+        let settings = JoinSettings::default();
+        let mut transitions = ramps(&[0.3, 0.3, 0.]);
+        transitions.sections_mut()[2].vert.function = TransitionFunction::Cubic;
+        let before = transitions.sections().to_vec();
+        assert_eq!(transitions.smooth_joins(&settings), 0);
+        assert_eq!(transitions.sections(), before);
+    }
+}
@@ -0,0 +1,397 @@
+use serde::{Deserialize, Serialize};
+
+const MIN_SPACING: f32 = 0.001; // smallest gap in t between neighbouring keys
+const CURVE_KEYS: usize = 64; // intervals when another curve is turned into keys
+const SLOPE_STEP: f32 = MIN_SPACING / 2.; // in t, for the slopes of a sampled curve
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub time: f32,  // 0 to 1 across the transition window
+    pub value: f32, // multiplied by the transition's change
+    pub slope: f32, // shared by both sides, so the curve stays smooth through the key
+}
+
+impl Keyframe {
+    pub fn new(time: f32, value: f32, slope: f32) -> Self {
+        Self { time, value, slope }
+    }
+}
+
+// a cubic hermite spline through the keys, kept in a form the solver can integrate:
+// it starts at 0 at t = 0 (no jump from the incoming level), ends at t = 1, and keys
+// are sorted and apart so every segment is a well defined cubic
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<Keyframe>", into = "Vec<Keyframe>")]
+pub struct Keyframes {
+    keys: Vec<Keyframe>,
+}
+
+impl Default for Keyframes {
+    // the same shape as smoothstep
+    fn default() -> Self {
+        Self::new(vec![Keyframe::new(0., 0., 0.), Keyframe::new(1., 1., 0.)])
+    }
+}
+
+impl From<Vec<Keyframe>> for Keyframes {
+    fn from(keys: Vec<Keyframe>) -> Self {
+        Self::new(keys)
+    }
+}
+
+impl From<Keyframes> for Vec<Keyframe> {
+    fn from(keyframes: Keyframes) -> Self {
+        keyframes.keys
+    }
+}
+
+impl Keyframes {
+    pub fn new(keys: Vec<Keyframe>) -> Self {
+        let mut keyframes = Self { keys };
+        keyframes.normalize();
+        keyframes
+    }
+
+    // keys close enough along curve (which starts at 0) that the cubics follow it to well
+    // within a thousandth, joins are where its pieces meet and get a key of their own, or
+    // one on each side when the slope jumps there
+    pub fn from_curve(curve: impl Fn(f32) -> f32, joins: &[f32]) -> Self {
+        let slope = |from: f32, to: f32| (curve(to) - curve(from)) / (to - from);
+        let near_join = |t: f32| joins.iter().any(|j| (t - j).abs() < 2. * MIN_SPACING);
+        let mut keys = (0..=CURVE_KEYS)
+            .map(|i| i as f32 / CURVE_KEYS as f32)
+            .filter(|&t| !near_join(t))
+            .map(|t| {
+                let (from, to) = ((t - SLOPE_STEP).max(0.), (t + SLOPE_STEP).min(1.));
+                Keyframe::new(t, curve(t), slope(from, to))
+            })
+            .collect::<Vec<_>>();
+        for &join in joins {
+            let before = slope(join - SLOPE_STEP, join);
+            let after = slope(join, join + SLOPE_STEP);
+            keys.push(Keyframe::new(join, curve(join), before));
+            if (after - before).abs() > SLOPE_STEP * (1. + before.abs()) {
+                let next = join + 2. * MIN_SPACING;
+                keys.push(Keyframe::new(
+                    next,
+                    curve(next),
+                    slope(next, next + SLOPE_STEP),
+                ));
+            }
+        }
+        Self::new(keys)
+    }
+
+    pub fn keys(&self) -> &[Keyframe] {
+        &self.keys
+    }
+
+    // the key may move past its neighbours, keys are re-sorted afterwards
+    pub fn set(&mut self, idx: usize, key: Keyframe) {
+        if let Some(existing) = self.keys.get_mut(idx) {
+            *existing = key;
+            self.normalize();
+        }
+    }
+
+    pub fn insert(&mut self, key: Keyframe) {
+        self.keys.push(key);
+        self.normalize();
+    }
+
+    // the first and last keys always stay
+    pub fn remove(&mut self, idx: usize) {
+        if idx > 0 && idx + 1 < self.keys.len() {
+            self.keys.remove(idx);
+        }
+    }
+
+    // a key on the curve at t, so adding it does not change the shape
+    pub fn key_at(&self, t: f32) -> Keyframe {
+        Keyframe::new(t, self.evaluate(t), self.slope(t))
+    }
+
+    // monotone slopes (fritsch-carlson), keeps the curve from overshooting between keys
+    pub fn auto_slopes(&mut self) {
+        let secants = self
+            .keys
+            .windows(2)
+            .map(|k| (k[1].value - k[0].value) / (k[1].time - k[0].time))
+            .collect::<Vec<_>>();
+        let last = self.keys.len() - 1;
+        for i in 0..=last {
+            // flat at the ends and at local extremes so they are not overshot
+            self.keys[i].slope = if i == 0 || i == last || secants[i - 1] * secants[i] <= 0. {
+                0.
+            } else {
+                // harmonic mean of the neighbouring secants
+                2. / (1. / secants[i - 1] + 1. / secants[i])
+            };
+        }
+    }
+
+    pub fn evaluate(&self, t: f32) -> f32 {
+        let (a, b, s) = self.segment(t);
+        let width = b.time - a.time;
+        let (s2, s3) = (s * s, s * s * s);
+        (2. * s3 - 3. * s2 + 1.) * a.value
+            + (s3 - 2. * s2 + s) * width * a.slope
+            + (-2. * s3 + 3. * s2) * b.value
+            + (s3 - s2) * width * b.slope
+    }
+
+    pub fn slope(&self, t: f32) -> f32 {
+        let (a, b, s) = self.segment(t);
+        let width = b.time - a.time;
+        let s2 = s * s;
+        ((6. * s2 - 6. * s) * a.value
+            + (3. * s2 - 4. * s + 1.) * width * a.slope
+            + (-6. * s2 + 6. * s) * b.value
+            + (3. * s2 - 2. * s) * width * b.slope)
+            / width
+    }
+
+    pub fn end_value(&self) -> f32 {
+        self.keys.last().map_or(0., |key| key.value)
+    }
+
+    // the curve between from and to stretched over [0, 1] and shifted to start at 0,
+    // exact because each piece of a cubic is the cubic through its end values and slopes
+    pub fn remap(&self, from: f32, to: f32) -> Keyframes {
+        let (from, to) = (from.clamp(0., 1.), to.clamp(0., 1.));
+        let width = to - from;
+        if width < MIN_SPACING {
+            return Keyframes::new(vec![Keyframe::new(0., 0., 0.), Keyframe::new(1., 0., 0.)]);
+        }
+        let start = self.evaluate(from);
+        let keys = std::iter::once(self.key_at(from))
+            .chain(
+                self.keys
+                    .iter()
+                    .copied()
+                    .filter(|key| key.time > from + MIN_SPACING && key.time < to - MIN_SPACING),
+            )
+            .chain(std::iter::once(self.key_at(to)))
+            .map(|key| {
+                Keyframe::new(
+                    (key.time - from) / width,
+                    key.value - start,
+                    key.slope * width,
+                )
+            })
+            .collect();
+        Keyframes::new(keys)
+    }
+
+    fn segment(&self, t: f32) -> (Keyframe, Keyframe, f32) {
+        let t = t.clamp(0., 1.);
+        let idx = self
+            .keys
+            .partition_point(|key| key.time <= t)
+            .clamp(1, self.keys.len() - 1);
+        let (a, b) = (self.keys[idx - 1], self.keys[idx]);
+        (a, b, (t - a.time) / (b.time - a.time))
+    }
+
+    fn normalize(&mut self) {
+        self.keys
+            .retain(|key| key.time.is_finite() && key.value.is_finite() && key.slope.is_finite());
+        for key in &mut self.keys {
+            key.time = key.time.clamp(0., 1.);
+        }
+        self.keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        // pin the ends, the first key sits on the incoming level
+        match self.keys.first_mut() {
+            Some(first) if first.time < MIN_SPACING => {
+                *first = Keyframe {
+                    time: 0.,
+                    value: 0.,
+                    ..*first
+                }
+            }
+            _ => self.keys.insert(0, Keyframe::new(0., 0., 0.)),
+        }
+        let last = *self.keys.last().unwrap();
+        if last.time > 1. - MIN_SPACING && self.keys.len() > 1 {
+            self.keys.last_mut().unwrap().time = 1.;
+        } else {
+            self.keys.push(Keyframe::new(1., last.value, 0.));
+        }
+
+        // drop keys crowding the one before them, the ends always win
+        let last = self.keys.len() - 1;
+        let mut kept: Vec<Keyframe> = Vec::with_capacity(self.keys.len());
+        for (i, key) in self.keys.iter().enumerate() {
+            match kept.last() {
+                Some(previous) if key.time - previous.time < MIN_SPACING => {
+                    if i == last {
+                        kept.pop();
+                        kept.push(*key);
+                    }
+                }
+                _ => kept.push(*key),
+            }
+        }
+        self.keys = kept;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn times(keyframes: &Keyframes) -> Vec<f32> {
+        keyframes.keys().iter().map(|key| key.time).collect()
+    }
+
+    // the largest difference from curve over a fine grid
+    fn error(keyframes: &Keyframes, curve: impl Fn(f32) -> f32) -> f32 {
+        (0..=1000)
+            .map(|i| i as f32 / 1000.)
+            .map(|t| (keyframes.evaluate(t) - curve(t)).abs())
+            .fold(0., f32::max)
+    }
+
+    #[test]
+    fn normalize_pins_the_ends_and_sorts() {
+        let keyframes = Keyframes::new(vec![
+            Keyframe::new(0.7, 2., 1.),
+            Keyframe::new(0.2, 1., 0.),
+            Keyframe::new(0.5, f32::NAN, 0.),
+            Keyframe::new(-0.5, 3., 0.5),
+        ]);
+        // the key before 0 is clamped onto it and pinned to 0, and an end is added at the
+        // last value
+        assert_eq!(
+            keyframes.keys(),
+            [
+                Keyframe::new(0., 0., 0.5),
+                Keyframe::new(0.2, 1., 0.),
+                Keyframe::new(0.7, 2., 1.),
+                Keyframe::new(1., 2., 0.),
+            ]
+        );
+        assert_eq!(keyframes.end_value(), 2.);
+
+        assert_eq!(times(&Keyframes::new(Vec::new())), [0., 1.]);
+        assert_eq!(Keyframes::new(Vec::new()).end_value(), 0.);
+
+        // crowded keys are dropped, the ends win
+        let crowded = Keyframes::new(vec![
+            Keyframe::new(0.0005, 1., 0.),
+            Keyframe::new(0.4, 1., 0.),
+            Keyframe::new(0.4005, 2., 0.),
+            Keyframe::new(0.9995, 3., 0.),
+            Keyframe::new(1.5, 4., 0.),
+        ]);
+        assert_eq!(
+            crowded.keys(),
+            [
+                Keyframe::new(0., 0., 0.),
+                Keyframe::new(0.4, 1., 0.),
+                Keyframe::new(1., 4., 0.),
+            ]
+        );
+    }
+
+    #[test]
+    fn from_curve_follows_the_curve() {
+        let smoothstep = |t: f32| t * t * (3. - 2. * t);
+        let keyframes = Keyframes::from_curve(smoothstep, &[]);
+        assert!(error(&keyframes, smoothstep) < 1e-3);
+
+        // a kink between two pieces keeps its corner
+        let tent = |t: f32| {
+            if t < 0.3 {
+                t * t * 5.
+            } else {
+                0.45 - (t - 0.3)
+            }
+        };
+        let keyframes = Keyframes::from_curve(tent, &[0.3]);
+        assert!(error(&keyframes, tent) < 1e-3);
+        assert!(keyframes.slope(0.299) > 2.9);
+        assert!(keyframes.slope(0.303) < -0.9);
+    }
+
+    #[test]
+    fn remap_keeps_the_shape() {
+        let keyframes = Keyframes::new(vec![
+            Keyframe::new(0.3, 1.2, 0.5),
+            Keyframe::new(0.6, -0.4, -1.),
+            Keyframe::new(1., 1., 0.),
+        ]);
+        let (from, to) = (0.2, 0.8);
+        let remapped = keyframes.remap(from, to);
+        let start = keyframes.evaluate(from);
+        assert!(
+            error(&remapped, |t| keyframes.evaluate(from + t * (to - from))
+                - start)
+                < 1e-5
+        );
+        assert_eq!(times(&remapped), [0., 1. / 6., 2. / 3., 1.]);
+
+        // the whole window is the same curve, an empty one is flat
+        assert!(error(&keyframes.remap(0., 1.), |t| keyframes.evaluate(t)) < 1e-6);
+        assert_eq!(
+            keyframes.remap(0.5, 0.5).keys(),
+            [Keyframe::new(0., 0., 0.), Keyframe::new(1., 0., 0.)]
+        );
+    }
+
+    #[test]
+    fn auto_slopes_do_not_overshoot() {
+        // rising unevenly, then a peak and a fall
+        let mut keyframes = Keyframes::new(vec![
+            Keyframe::new(0.1, 0.9, 0.),
+            Keyframe::new(0.15, 1., 0.),
+            Keyframe::new(0.5, 1.1, 0.),
+            Keyframe::new(0.6, 3., 0.),
+            Keyframe::new(1., 0.5, 0.),
+        ]);
+        keyframes.auto_slopes();
+        let keys = keyframes.keys().to_vec();
+        // flat at the ends and the peak, and between the secants elsewhere
+        assert_eq!(keys[0].slope, 0.);
+        assert_eq!(keys[4].slope, 0.);
+        assert_eq!(keys[5].slope, 0.);
+        for i in 1..4 {
+            let before = (keys[i].value - keys[i - 1].value) / (keys[i].time - keys[i - 1].time);
+            let after = (keys[i + 1].value - keys[i].value) / (keys[i + 1].time - keys[i].time);
+            assert!(keys[i].slope >= before.min(after) && keys[i].slope <= before.max(after));
+        }
+
+        // between any two keys the curve stays monotone and within their values
+        for pair in keys.windows(2) {
+            let (low, high) = (
+                pair[0].value.min(pair[1].value),
+                pair[0].value.max(pair[1].value),
+            );
+            let rising = pair[1].value >= pair[0].value;
+            let mut previous = pair[0].value;
+            for i in 1..=100 {
+                let t = pair[0].time + (pair[1].time - pair[0].time) * i as f32 / 100.;
+                let value = keyframes.evaluate(t);
+                assert!(
+                    value >= low - 1e-5 && value <= high + 1e-5,
+                    "{}: {}",
+                    t,
+                    value
+                );
+                assert!(
+                    if rising {
+                        value >= previous - 1e-5
+                    } else {
+                        value <= previous + 1e-5
+                    },
+                    "{}: {} after {}",
+                    t,
+                    value,
+                    previous
+                );
+                previous = value;
+            }
+        }
+    }
+}
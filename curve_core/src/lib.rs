@@ -1,4 +1,15 @@
+pub mod clearance;
+pub mod expression;
+pub mod fit;
 pub mod fvd;
+pub mod groups;
+pub mod history;
+pub mod joins;
+pub mod keyframes;
+pub mod params;
+pub mod project;
 pub mod spline;
+pub mod templates;
 pub mod transitions;
 pub mod units;
+pub mod validate;
@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::transitions::{FullTransition, Transitions};
+
+// named design variables, e.g. hill_g or turn_time
+pub type Parameters = BTreeMap<String, f32>;
+
+// parameter names driving a section's values, bound values are still stored in the section
+// so the solver never needs the parameters, and a missing name keeps the last value
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Bindings {
+    pub length: Option<String>,
+    pub vert: Option<String>, // change of each transition
+    pub lat: Option<String>,
+    pub roll: Option<String>,
+}
+
+impl Bindings {
+    pub fn is_empty(&self) -> bool {
+        self.names().next().is_none()
+    }
+
+    pub fn uses(&self, name: &str) -> bool {
+        self.names().any(|n| n == name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        [&self.length, &self.vert, &self.lat, &self.roll]
+            .into_iter()
+            .filter_map(|name| name.as_deref())
+    }
+}
+
+impl FullTransition {
+    // a copy with the bound values taken from the parameters
+    fn bound(&self, parameters: &Parameters) -> FullTransition {
+        let value = |name: &Option<String>| name.as_ref().and_then(|n| parameters.get(n)).copied();
+        let mut section = self.clone();
+        if let Some(length) = value(&self.bindings.length).filter(|&length| length > 0.) {
+            section.length = length;
+        }
+        for (transition, name) in [
+            (&mut section.vert, &self.bindings.vert),
+            (&mut section.lat, &self.bindings.lat),
+            (&mut section.roll, &self.bindings.roll),
+        ] {
+            if let Some(change) = value(name) {
+                transition.change = change;
+            }
+        }
+        section
+    }
+
+    // bound values that changed while their binding stayed the same
+    pub(crate) fn bound_edits(&self, after: &FullTransition) -> Vec<(String, f32)> {
+        [
+            (
+                &self.bindings.length,
+                &after.bindings.length,
+                self.length,
+                after.length,
+            ),
+            (
+                &self.bindings.vert,
+                &after.bindings.vert,
+                self.vert.change,
+                after.vert.change,
+            ),
+            (
+                &self.bindings.lat,
+                &after.bindings.lat,
+                self.lat.change,
+                after.lat.change,
+            ),
+            (
+                &self.bindings.roll,
+                &after.bindings.roll,
+                self.roll.change,
+                after.roll.change,
+            ),
+        ]
+        .into_iter()
+        .filter_map(
+            |(binding, binding_after, value, value_after)| match binding {
+                Some(name) if binding == binding_after && value != value_after => {
+                    Some((name.clone(), value_after))
+                }
+                _ => None,
+            },
+        )
+        .collect()
+    }
+}
+
+impl Transitions {
+    // writes parameter values into every bound length and change
+    pub fn apply_parameters(&mut self, parameters: &Parameters) {
+        let changed = self
+            .sections()
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, section)| {
+                let bound = section.bound(parameters);
+                (bound != *section).then_some((idx, bound))
+            })
+            .collect::<Vec<_>>();
+        // only invalidate the force table when something actually moved
+        if changed.is_empty() {
+            return;
+        }
+        let sections = self.sections_mut();
+        for (idx, section) in changed {
+            sections[idx] = section;
+        }
+    }
+
+    pub fn parameter_users(&self, name: &str) -> usize {
+        self.sections()
+            .iter()
+            .filter(|section| section.bindings.uses(name))
+            .count()
+    }
+}
@@ -0,0 +1,428 @@
+use std::fmt;
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    fvd::{self, Curve, RollAxis, Track, HEARTLINE_HEIGHT},
+    joins::JoinSettings,
+    params::Parameters,
+    spline::INTERVAL,
+    transitions::{TransitionFunction, Transitions},
+    units::{Meters, MetersPerSec, UnitSystem},
+    validate::{DesignLimits, Diagnostic, Field, Issue},
+};
+
+pub const PROJECT_VERSION: u32 = 2;
+
+// MIGRATIONS[n] upgrades a version n + 1 project to version n + 2
+const MIGRATIONS: &[fn(Value) -> Value] = &[migrate_plateau_shape];
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub name: String,
+    pub author: String,
+    pub description: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StartState {
+    pub position: Vec3,
+    pub velocity: f32, // m/s
+}
+
+impl Default for StartState {
+    fn default() -> Self {
+        Self {
+            position: Vec3::Y,
+            velocity: 5.,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SimulationSettings {
+    pub heartline_height: f32,
+    pub roll_axis: RollAxis,
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        Self {
+            heartline_height: HEARTLINE_HEIGHT.0,
+            roll_axis: RollAxis::Heartline,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Project {
+    pub version: u32,
+    pub metadata: Metadata,
+    pub start: StartState,
+    pub settings: SimulationSettings,
+    #[serde(default)]
+    pub parameters: Parameters,
+    #[serde(default)]
+    pub limits: DesignLimits,
+    #[serde(default)]
+    pub joins: JoinSettings,
+    #[serde(default)]
+    pub units: UnitSystem, // for input and display only
+    pub transitions: Transitions,
+}
+
+#[derive(Debug)]
+pub enum ProjectError {
+    Json(serde_json::Error),
+    MissingVersion,
+    InvalidVersion(u32), // versions start at 1
+    UnsupportedVersion(u32),
+    NoSections,
+    InvalidLength { section: usize, length: f32 },
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProjectError::Json(err) => write!(f, "invalid project file: {}", err),
+            ProjectError::MissingVersion => write!(f, "project file has no version"),
+            ProjectError::InvalidVersion(version) => {
+                write!(f, "project version {} is not a valid version", version)
+            }
+            ProjectError::UnsupportedVersion(version) => write!(
+                f,
+                "project version {} is newer than the supported version {}",
+                version, PROJECT_VERSION
+            ),
+            ProjectError::NoSections => write!(f, "project has no sections"),
+            ProjectError::InvalidLength { section, length } => write!(
+                f,
+                "section {} has length {}, lengths must be positive",
+                section + 1,
+                length
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProjectError {}
+
+impl From<serde_json::Error> for ProjectError {
+    fn from(err: serde_json::Error) -> Self {
+        ProjectError::Json(err)
+    }
+}
+
+impl Project {
+    pub fn new(transitions: Transitions) -> Self {
+        Self {
+            version: PROJECT_VERSION,
+            metadata: Metadata::default(),
+            start: StartState::default(),
+            settings: SimulationSettings::default(),
+            parameters: Parameters::new(),
+            limits: DesignLimits::default(),
+            joins: JoinSettings::default(),
+            units: UnitSystem::default(),
+            transitions,
+        }
+    }
+
+    pub fn create_spline(&self) -> Track {
+        fvd::create_spline(
+            &self.transitions,
+            self.start.position,
+            MetersPerSec(self.start.velocity),
+            Meters(self.settings.heartline_height),
+            self.settings.roll_axis,
+        )
+    }
+
+    // None removes the parameter, sections bound to it keep their last value
+    pub fn set_parameter(&mut self, name: &str, value: Option<f32>) {
+        match value {
+            Some(value) => {
+                self.parameters.insert(name.to_string(), value);
+            }
+            None => {
+                self.parameters.remove(name);
+            }
+        }
+        self.transitions.apply_parameters(&self.parameters);
+    }
+
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.transitions.validate(&self.limits);
+        let velocity = self.start.velocity;
+        if !velocity.is_finite() || velocity <= 0. {
+            diagnostics.insert(
+                0,
+                Diagnostic {
+                    section: None,
+                    field: Field::Speed,
+                    issue: if velocity.is_finite() {
+                        Issue::InvalidSpeed(velocity)
+                    } else {
+                        Issue::NotFinite
+                    },
+                },
+            );
+        }
+        diagnostics
+    }
+
+    pub fn export_description(&self) -> String {
+        let name = if self.metadata.name.is_empty() {
+            "elimerl's fvd export"
+        } else {
+            &self.metadata.name
+        };
+        let sections = self
+            .transitions
+            .sections()
+            .iter()
+            .enumerate()
+            .filter(|(_, section)| section.info.name.is_some() || section.info.tag.is_some())
+            .map(|(idx, section)| section.label(idx))
+            .collect::<Vec<_>>();
+        if sections.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}: {}", name, sections.join(", "))
+        }
+    }
+
+    // an nl2elem file of one of the track's curves, with points at the export spacing
+    pub fn export_nolimits(&self, track: &Track, curve: Curve) -> String {
+        track
+            .curve(curve)
+            .resample(INTERVAL)
+            .to_nolimits_element(&self.export_description())
+    }
+
+    pub fn to_json(&self) -> Result<String, ProjectError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, ProjectError> {
+        let value = migrate(serde_json::from_str(json)?)?;
+        let project: Project = serde_json::from_value(value)?;
+        project.check_sections()?;
+        Ok(project)
+    }
+
+    // the solver needs at least one section and can't step through an empty one
+    fn check_sections(&self) -> Result<(), ProjectError> {
+        let sections = self.transitions.sections();
+        if sections.is_empty() {
+            return Err(ProjectError::NoSections);
+        }
+        match sections
+            .iter()
+            .position(|section| !(section.length.is_finite() && section.length > 0.))
+        {
+            Some(section) => Err(ProjectError::InvalidLength {
+                section,
+                length: sections[section].length,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn migrate(mut value: Value) -> Result<Value, ProjectError> {
+    let mut version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or(ProjectError::MissingVersion)? as u32;
+    if version == 0 {
+        return Err(ProjectError::InvalidVersion(version));
+    }
+    if version > PROJECT_VERSION {
+        return Err(ProjectError::UnsupportedVersion(version));
+    }
+
+    while version < PROJECT_VERSION {
+        value = MIGRATIONS[version as usize - 1](value);
+        version += 1;
+        value["version"] = version.into();
+    }
+    Ok(value)
+}
+
+// v2 gave Plateau its ramp parameters, v1 files store it as a bare "Plateau"
+fn migrate_plateau_shape(mut value: Value) -> Value {
+    let plateau = serde_json::to_value(TransitionFunction::PLATEAU).unwrap();
+    if let Some(sections) = value["transitions"]["transitions"].as_array_mut() {
+        for section in sections {
+            for axis in ["vert", "lat", "roll"] {
+                let function = &mut section[axis]["function"];
+                if function == "Plateau" {
+                    *function = plateau.clone();
+                }
+            }
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        expression::Expression,
+        keyframes::{Keyframe, Keyframes},
+        transitions::{ElementTag, FullTransition, SectionInfo, Transition},
+        units::{m_to_ft, DegPerSec, GForce},
+    };
+
+    fn project() -> Project {
+        let mut transitions = Transitions::new(GForce(1.), GForce(0.), DegPerSec(0.));
+        *transitions.sections_mut() = vec![
+            FullTransition {
+                vert: Transition::new(
+                    TransitionFunction::Expression(
+                        Expression::parse("t * t * (3 - 2 * t)").unwrap(),
+                    ),
+                    2.,
+                ),
+                length: 2.,
+                ..FullTransition::default()
+            },
+            FullTransition {
+                lat: Transition::new(
+                    TransitionFunction::Keyframes(Keyframes::new(vec![
+                        Keyframe::new(0., 0., 0.),
+                        Keyframe::new(0.4, 1.2, 0.5),
+                        Keyframe::new(1., 1., 0.),
+                    ])),
+                    -0.5,
+                ),
+                roll: Transition::new(TransitionFunction::PLATEAU, 90.),
+                length: 3.,
+                ..FullTransition::default()
+            },
+        ];
+        let mut project = Project::new(transitions);
+        project.metadata.name = "test".to_string();
+        project
+    }
+
+    fn edit(project: &Project, change: impl FnOnce(&mut Value)) -> String {
+        let mut value = serde_json::to_value(project).unwrap();
+        change(&mut value);
+        value.to_string()
+    }
+
+    #[test]
+    fn round_trip() {
+        let project = project();
+        let loaded = Project::from_json(&project.to_json().unwrap()).unwrap();
+        assert_eq!(loaded.version, PROJECT_VERSION);
+        assert_eq!(loaded.metadata, project.metadata);
+        assert_eq!(loaded.start, project.start);
+        assert_eq!(loaded.settings, project.settings);
+        assert_eq!(
+            loaded.transitions.sections(),
+            project.transitions.sections()
+        );
+        assert_eq!(
+            loaded.transitions.start_values(0),
+            project.transitions.start_values(0)
+        );
+    }
+
+    #[test]
+    fn migrates_v1_plateaus() {
+        let project = project();
+        let json = edit(&project, |value| {
+            value["version"] = 1.into();
+            value["transitions"]["transitions"][1]["roll"]["function"] = "Plateau".into();
+        });
+        let loaded = Project::from_json(&json).unwrap();
+        assert_eq!(loaded.version, PROJECT_VERSION);
+        assert_eq!(
+            loaded.transitions.sections(),
+            project.transitions.sections()
+        );
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let project = project();
+        let load = |change: fn(&mut Value)| Project::from_json(&edit(&project, change));
+        assert!(matches!(
+            load(|value| value["version"] = 0.into()),
+            Err(ProjectError::InvalidVersion(0))
+        ));
+        assert!(matches!(
+            load(|value| value["version"] = (PROJECT_VERSION + 1).into()),
+            Err(ProjectError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            load(|value| {
+                value.as_object_mut().unwrap().remove("version");
+            }),
+            Err(ProjectError::MissingVersion)
+        ));
+        assert!(matches!(
+            load(|value| value["transitions"]["transitions"] = Value::Array(vec![])),
+            Err(ProjectError::NoSections)
+        ));
+        assert!(matches!(
+            load(|value| value["transitions"]["transitions"][1]["length"] = 0.into()),
+            Err(ProjectError::InvalidLength { section: 1, .. })
+        ));
+        assert!(matches!(
+            load(|value| value["transitions"]["transitions"][0]["length"] = (-1.).into()),
+            Err(ProjectError::InvalidLength { section: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn export_description_names_the_sections() {
+        let mut project = project();
+        assert_eq!(project.export_description(), "test");
+        project.metadata.name.clear();
+        assert_eq!(project.export_description(), "elimerl's fvd export");
+
+        project.metadata.name = "test".to_string();
+        project.transitions.sections_mut()[1].info = SectionInfo {
+            name: Some("first drop".to_string()),
+            tag: Some(ElementTag::Drop),
+            ..SectionInfo::default()
+        };
+        assert_eq!(project.export_description(), "test: first drop (Drop)");
+        project.transitions.sections_mut()[0].info.tag = Some(ElementTag::Launch);
+        assert_eq!(
+            project.export_description(),
+            "test: Section 1 (Launch), first drop (Drop)"
+        );
+
+        let track = project.create_spline();
+        let element = project.export_nolimits(&track, Curve::Heartline);
+        assert!(element
+            .contains("<description>test: Section 1 (Launch), first drop (Drop)</description>"));
+        // one vertex at each end and every INTERVAL between
+        let vertices = element.matches("<vertex>").count();
+        let expected = (track.heartline.length() / INTERVAL).ceil() as usize + 1;
+        assert_eq!(vertices, expected);
+    }
+
+    #[test]
+    fn exports_either_curve() {
+        let project = project();
+        let track = project.create_spline();
+        // the height of the first vertex, in feet
+        let first_y = |curve| {
+            let element = project.export_nolimits(&track, curve);
+            let y = &element[element.find("<y>").unwrap() + 3..];
+            y[..y.find('<').unwrap()].parse::<f32>().unwrap()
+        };
+        let drop = first_y(Curve::Heartline) - first_y(Curve::TrackCentre);
+        let height = m_to_ft(project.settings.heartline_height);
+        assert!((drop - height).abs() < 1e-3, "{} != {}", drop, height);
+    }
+}
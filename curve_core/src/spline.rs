@@ -1,66 +1,266 @@
-use glam::{Quat, Vec3};
+use std::cell::OnceCell;
+
+use glam::{Mat3, Quat, Vec3};
 use xmlwriter::XmlWriter;
 
 use crate::{
-    fvd::{RIGHT, UP},
-    units::m_to_ft_vec3,
+    fvd::{FORWARD, RIGHT, UP},
+    units::{m_to_ft_vec3, Meters, Seconds},
 };
 
-pub const INTERVAL: f32 = 0.3; // m between exported points
+pub const INTERVAL: Meters = Meters(0.3); // between exported points
+
+const KNOT_EPSILON: f32 = 1e-4; // keeps repeated points from dividing by zero
+const ARC_TOLERANCE: f32 = 1e-5; // m, close enough when finding a distance within a segment
+const ARC_STEPS: usize = 8; // newton steps, each segment is short so few are ever needed
+
+// 5 point gauss-legendre nodes and weights on [-1, 1], for the arc length of a segment
+const GAUSS_LEGENDRE: [(f32, f32); 5] = [
+    (0., 0.568_888_9),
+    (-0.538_469_3, 0.478_628_7),
+    (0.538_469_3, 0.478_628_7),
+    (-0.906_179_8, 0.236_926_9),
+    (0.906_179_8, 0.236_926_9),
+];
 
 #[derive(Debug, Clone)]
 pub struct TrackSpline {
-    pub points: Vec<(Vec3, Quat)>, // pos, orientation
+    points: Vec<(Vec3, Quat)>,     // pos, orientation
+    times: Vec<f32>,               // s into the ride at each point, empty if not simulated
+    distances: OnceCell<Vec<f32>>, // arc length along the curve up to each point, rebuilt lazily after any mutable access
+}
+
+impl Default for TrackSpline {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TrackSpline {
     pub fn new() -> Self {
-        TrackSpline { points: Vec::new() }
+        TrackSpline {
+            points: Vec::new(),
+            times: Vec::new(),
+            distances: OnceCell::new(),
+        }
     }
 
-    pub fn length(&self) -> f32 {
-        self.points
-            .windows(2)
-            .map(|p| (p[0].0 - p[1].0).length())
-            .sum()
+    pub fn from_points(points: Vec<(Vec3, Quat)>) -> Self {
+        TrackSpline {
+            points,
+            ..TrackSpline::new()
+        }
     }
 
-    pub fn evaluate(&self, t: f32) -> Option<(Vec3, Quat)> {
-        if self.points.len() < 2 {
-            return None; // Need at least 2 points for a spline
+    pub fn points(&self) -> &[(Vec3, Quat)] {
+        &self.points
+    }
+    // timestamps no longer line up once points are edited, so they are dropped
+    pub fn points_mut(&mut self) -> &mut Vec<(Vec3, Quat)> {
+        self.distances.take();
+        self.times.clear();
+        &mut self.points
+    }
+    // a simulated point, times must increase
+    pub fn push(&mut self, pos: Vec3, rot: Quat, time: Seconds) {
+        self.distances.take();
+        // a spline with untimed points stays untimed
+        if self.times.len() == self.points.len() {
+            self.times.push(time.0);
         }
+        self.points.push((pos, rot));
+    }
+    pub fn times(&self) -> &[f32] {
+        &self.times
+    }
+    pub fn distances(&self) -> &[f32] {
+        self.distances.get_or_init(|| {
+            let mut length = 0.;
+            let mut distances = Vec::with_capacity(self.points.len());
+            for i in 0..self.points.len() {
+                if i > 0 {
+                    length += self.segment(i).arc_length(1.);
+                }
+                distances.push(length);
+            }
+            distances
+        })
+    }
 
-        let t_clamped = (t / self.length()).clamp(0., 1.);
+    pub fn length(&self) -> Meters {
+        Meters(self.distances().last().copied().unwrap_or(0.))
+    }
 
-        let segments = self.points.len() - 1;
-        let segment_idx = (t_clamped * segments as f32).floor() as usize;
-        let t_in_segment = t_clamped * segments as f32 - segment_idx as f32;
+    pub fn offset(&self, height: Meters) -> TrackSpline {
+        let Meters(height) = height;
+        TrackSpline {
+            points: self
+                .points
+                .iter()
+                .map(|&(pos, rot)| (pos + rot * UP * height, rot))
+                .collect(),
+            times: self.times.clone(),
+            distances: OnceCell::new(),
+        }
+    }
 
-        if let (Some(start), Some(end)) = (
-            self.points.get(segment_idx),
-            self.points.get(segment_idx + 1),
-        ) {
-            let interpolated_point = Vec3::new(
-                start.0.x + t_in_segment * (end.0.x - start.0.x),
-                start.0.y + t_in_segment * (end.0.y - start.0.y),
-                start.0.z + t_in_segment * (end.0.z - start.0.z),
-            );
+    // reflection about the plane through point, e.g. the other side of a dueling layout
+    pub fn mirror(&self, point: Vec3, normal: Vec3) -> TrackSpline {
+        let normal = normal.normalize();
+        let reflect = |v: Vec3| v - 2. * v.dot(normal) * normal;
+        TrackSpline {
+            points: self
+                .points
+                .iter()
+                .map(|&(pos, rot)| {
+                    // a reflection flips handedness, so right is negated to keep a rotation
+                    let basis = Mat3::from_cols(
+                        -reflect(rot * RIGHT),
+                        reflect(rot * UP),
+                        reflect(rot * FORWARD),
+                    );
+                    (
+                        point + reflect(pos - point),
+                        Quat::from_mat3(&basis).normalize(),
+                    )
+                })
+                .collect(),
+            times: self.times.clone(),
+            distances: OnceCell::new(),
+        }
+    }
 
-            Some((interpolated_point, start.1.slerp(end.1, t_in_segment)))
+    // the point the given distance along the spline, clamped to its ends
+    pub fn evaluate(&self, distance: Meters) -> Option<(Vec3, Quat)> {
+        let (idx, u) = self.locate_distance(distance.0)?;
+        Some((self.segment(idx).position(u), self.orientation(idx, u)))
+    }
+
+    // the point the train reached at the given time, for splines from the simulation
+    pub fn evaluate_time(&self, time: Seconds) -> Option<(Vec3, Quat)> {
+        if self.times.len() != self.points.len() {
+            return None;
+        }
+        let (idx, u) = self.locate(&self.times, time.0)?;
+        Some((self.segment(idx).position(u), self.orientation(idx, u)))
+    }
+
+    // unit direction of travel
+    pub fn tangent(&self, distance: Meters) -> Option<Vec3> {
+        let (idx, u) = self.locate_distance(distance.0)?;
+        Some(self.segment(idx).velocity(u).normalize_or_zero())
+    }
+
+    // 1/m, the inverse of the radius of the turn at that point
+    pub fn curvature(&self, distance: Meters) -> Option<f32> {
+        let (idx, u) = self.locate_distance(distance.0)?;
+        let segment = self.segment(idx);
+        let (velocity, acceleration) = (segment.velocity(u), segment.acceleration(u));
+        let speed = velocity.length();
+        Some(if speed > 0. {
+            velocity.cross(acceleration).length() / (speed * speed * speed)
         } else {
-            None
+            0.
+        })
+    }
+
+    // evenly spaced points along the smooth curve, e.g. for an export at a coarser spacing
+    pub fn resample(&self, interval: Meters) -> TrackSpline {
+        let length = self.length();
+        if interval.0 <= 0. || self.points.len() < 2 {
+            return self.clone();
         }
+        let count = (length / interval).ceil() as usize;
+        TrackSpline::from_points(
+            (0..=count)
+                .filter_map(|i| self.evaluate(Meters((i as f32 * interval.0).min(length.0))))
+                .collect(),
+        )
+    }
+
+    // the segment ending at the returned point, and how far through it value is, for keys
+    // increasing from point to point
+    fn locate(&self, keys: &[f32], value: f32) -> Option<(usize, f32)> {
+        if self.points.len() < 2 {
+            return None; // Need at least 2 points for a spline
+        }
+        let idx = keys
+            .partition_point(|&key| key <= value)
+            .clamp(1, self.points.len() - 1);
+        let span = keys[idx] - keys[idx - 1];
+        let u = if span > 0. {
+            ((value - keys[idx - 1]) / span).clamp(0., 1.)
+        } else {
+            0.
+        };
+        Some((idx, u))
     }
 
-    pub fn to_nolimits_element(&self) -> String {
-        let mut export_points = Vec::new();
-        let mut interval = 0.;
-        for points in self.points.windows(2) {
-            interval += (points[0].0 - points[1].0).length();
-            if interval > INTERVAL {
-                export_points.push(points[0]);
+    // like locate, but u is found so the point is that far along the curve itself rather
+    // than that fraction of the chord, which keeps resampled points evenly spaced
+    fn locate_distance(&self, distance: f32) -> Option<(usize, f32)> {
+        let distances = self.distances();
+        let (idx, mut u) = self.locate(distances, distance)?;
+        let segment = self.segment(idx);
+        let target = (distance - distances[idx - 1]).clamp(0., distances[idx] - distances[idx - 1]);
+        // newton's method on the arc length, kept inside a shrinking bracket
+        let (mut low, mut high) = (0., 1.);
+        for _ in 0..ARC_STEPS {
+            let error = segment.arc_length(u) - target;
+            if error.abs() < ARC_TOLERANCE {
+                break;
             }
+            if error > 0. {
+                high = u;
+            } else {
+                low = u;
+            }
+            let next = u - error / segment.velocity(u).length();
+            u = if next > low && next < high {
+                next
+            } else {
+                (low + high) / 2.
+            };
         }
+        Some((idx, u))
+    }
+
+    // centripetal catmull-rom between points idx - 1 and idx, written as a cubic hermite so
+    // its derivatives are exact; the ends use a reflected neighbour
+    fn segment(&self, idx: usize) -> Segment {
+        let p1 = self.points[idx - 1].0;
+        let p2 = self.points[idx].0;
+        let p0 = match idx {
+            1 => 2. * p1 - p2,
+            _ => self.points[idx - 2].0,
+        };
+        let p3 = self
+            .points
+            .get(idx + 1)
+            .map_or(2. * p2 - p1, |point| point.0);
+
+        // knot spacing is the square root of the chord length (alpha = 0.5)
+        let knot = |a: Vec3, b: Vec3| a.distance(b).sqrt().max(KNOT_EPSILON);
+        let (d0, d1, d2) = (knot(p0, p1), knot(p1, p2), knot(p2, p3));
+        let m1 = d1 * ((p1 - p0) / d0 - (p2 - p0) / (d0 + d1) + (p2 - p1) / d1);
+        let m2 = d1 * ((p2 - p1) / d1 - (p3 - p1) / (d1 + d2) + (p3 - p2) / d2);
+        Segment { p1, p2, m1, m2 }
+    }
+
+    // squad between the orientations at idx - 1 and idx, smooth through every sample
+    fn orientation(&self, idx: usize, u: f32) -> Quat {
+        let q1 = self.points[idx - 1].1;
+        let q2 = hemisphere(self.points[idx].1, q1);
+        let q0 = hemisphere(self.points[idx.saturating_sub(2)].1, q1);
+        let q3 = hemisphere(self.points.get(idx + 1).map_or(q2, |point| point.1), q2);
+        let s1 = squad_control(q0, q1, q2);
+        let s2 = squad_control(q1, q2, q3);
+        q1.slerp(q2, u)
+            .slerp(s1.slerp(s2, u), 2. * u * (1. - u))
+            .normalize()
+    }
+
+    pub fn to_nolimits_element(&self, description: &str) -> String {
         let opt = xmlwriter::Options {
             indent: xmlwriter::Indent::None,
             ..xmlwriter::Options::default()
@@ -72,7 +272,7 @@ impl TrackSpline {
         w.start_element("root");
         w.start_element("element");
         w.start_element("description");
-        w.write_text("elimerl's fvd export");
+        w.write_text(description);
         w.end_element();
         for (i, point) in self.points.iter().enumerate() {
             let pos = m_to_ft_vec3(point.0);
@@ -92,10 +292,8 @@ impl TrackSpline {
             w.end_element();
             w.end_element();
         }
-        let mut length_so_far = 0.;
-        for points in self.points.windows(2) {
+        for (i, points) in self.points.windows(2).enumerate() {
             let point = points[0];
-            let next_point = points[1];
 
             w.start_element("roll");
 
@@ -123,7 +321,7 @@ impl TrackSpline {
             w.end_element();
 
             w.start_element("coord");
-            w.write_text_fmt(format_args!("{:.5}", length_so_far / self.length()));
+            w.write_text_fmt(format_args!("{:.5}", self.distances()[i] / self.length().0));
             w.end_element();
 
             w.start_element("strict");
@@ -131,8 +329,245 @@ impl TrackSpline {
             w.end_element();
 
             w.end_element();
-            length_so_far += (next_point.0 - point.0).length();
         }
         w.end_document()
     }
 }
+
+// a cubic hermite segment over u in [0, 1]
+struct Segment {
+    p1: Vec3,
+    p2: Vec3,
+    m1: Vec3, // tangents at p1 and p2, per unit u
+    m2: Vec3,
+}
+
+impl Segment {
+    fn position(&self, u: f32) -> Vec3 {
+        let (u2, u3) = (u * u, u * u * u);
+        (2. * u3 - 3. * u2 + 1.) * self.p1
+            + (u3 - 2. * u2 + u) * self.m1
+            + (-2. * u3 + 3. * u2) * self.p2
+            + (u3 - u2) * self.m2
+    }
+
+    fn velocity(&self, u: f32) -> Vec3 {
+        let u2 = u * u;
+        (6. * u2 - 6. * u) * self.p1
+            + (3. * u2 - 4. * u + 1.) * self.m1
+            + (-6. * u2 + 6. * u) * self.p2
+            + (3. * u2 - 2. * u) * self.m2
+    }
+
+    // length of the curve from 0 to u
+    fn arc_length(&self, u: f32) -> f32 {
+        GAUSS_LEGENDRE
+            .iter()
+            .map(|&(node, weight)| weight * self.velocity(u * (node + 1.) / 2.).length())
+            .sum::<f32>()
+            * u
+            / 2.
+    }
+
+    fn acceleration(&self, u: f32) -> Vec3 {
+        (12. * u - 6.) * self.p1
+            + (6. * u - 4.) * self.m1
+            + (-12. * u + 6.) * self.p2
+            + (6. * u - 2.) * self.m2
+    }
+}
+
+// q or -q, whichever is on the same side as reference, so interpolation takes the short way
+fn hemisphere(q: Quat, reference: Quat) -> Quat {
+    if q.dot(reference) < 0. {
+        -q
+    } else {
+        q
+    }
+}
+
+// the inner control point of squad at q1, q1 * exp(-(log(q1^-1 q2) + log(q1^-1 q0)) / 4)
+fn squad_control(q0: Quat, q1: Quat, q2: Quat) -> Quat {
+    let inverse = q1.inverse();
+    let tangent = -(log(inverse * q2) + log(inverse * q0)) / 4.;
+    (q1 * exp(tangent)).normalize()
+}
+
+// logarithm of a unit quaternion, half the rotation angle along the axis
+fn log(q: Quat) -> Vec3 {
+    let q = if q.w < 0. { -q } else { q };
+    let v = Vec3::new(q.x, q.y, q.z);
+    let sin = v.length();
+    if sin < 1e-6 {
+        return v;
+    }
+    v / sin * sin.atan2(q.w)
+}
+
+fn exp(v: Vec3) -> Quat {
+    let angle = v.length();
+    if angle < 1e-6 {
+        return Quat::from_xyzw(v.x, v.y, v.z, 1.).normalize();
+    }
+    let axis = v / angle * angle.sin();
+    Quat::from_xyzw(axis.x, axis.y, axis.z, angle.cos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a quarter circle of radius 10 with a point every 15 degrees, far enough apart that the
+    // chords are noticeably shorter than the curve
+    fn arc() -> TrackSpline {
+        TrackSpline::from_points(
+            (0..=6)
+                .map(|i| {
+                    let angle = (i as f32 * 15.).to_radians();
+                    (
+                        Vec3::new(angle.cos(), angle.sin(), 0.) * 10.,
+                        Quat::IDENTITY,
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn distances_follow_the_curve() {
+        let spline = arc();
+        let quarter = std::f32::consts::FRAC_PI_2 * 10.;
+        assert!((spline.length().0 - quarter).abs() < 0.01 * quarter);
+
+        // the curve between evenly spaced distances is evenly long, measured finely
+        let steps = 100;
+        let step = spline.length().0 / steps as f32;
+        let mut previous = spline.evaluate(Meters(0.)).unwrap().0;
+        for i in 1..=steps {
+            let mut walked = 0.;
+            for j in 1..=10 {
+                let point = spline
+                    .evaluate(Meters((i - 1) as f32 * step + j as f32 * step / 10.))
+                    .unwrap()
+                    .0;
+                walked += point.distance(previous);
+                previous = point;
+            }
+            assert!(
+                (walked - step).abs() < 1e-3,
+                "step {}: {} != {}",
+                i,
+                walked,
+                step
+            );
+        }
+    }
+
+    // points every degrees around a circle of radius, rolling about the direction of travel
+    // by roll degrees per point, and timed as if ridden at 1 m/s
+    fn circle(radius: f32, degrees: f32, count: usize, roll: f32) -> TrackSpline {
+        let mut spline = TrackSpline::new();
+        for i in 0..=count {
+            let angle = (i as f32 * degrees).to_radians();
+            let heading = Quat::from_rotation_y(-angle);
+            let rot = heading * Quat::from_rotation_z((i as f32 * roll).to_radians());
+            // every other sign flipped, both describe the same orientation
+            let rot = if i % 2 == 0 { rot } else { -rot };
+            spline.push(
+                Vec3::new(1. - angle.cos(), 0., angle.sin()) * radius,
+                rot,
+                Seconds(angle * radius),
+            );
+        }
+        spline
+    }
+
+    #[test]
+    fn tangent_and_curvature_follow_a_circle() {
+        let radius = 10.;
+        let spline = circle(radius, 2., 90, 0.);
+        // the end segments use a reflected neighbour, so only the inner ones are checked
+        let inner = spline.distances()[1]..spline.distances()[88];
+        for i in 0..=50 {
+            let distance = inner.start + (inner.end - inner.start) * i as f32 / 50.;
+            let angle = distance / radius;
+            let tangent = spline.tangent(Meters(distance)).unwrap();
+            let expected = Vec3::new(angle.sin(), 0., angle.cos());
+            assert!(
+                tangent.distance(expected) < 1e-3,
+                "{}: {} != {}",
+                distance,
+                tangent,
+                expected
+            );
+            let curvature = spline.curvature(Meters(distance)).unwrap();
+            assert!(
+                (curvature * radius - 1.).abs() < 0.01,
+                "{}: {} != {}",
+                distance,
+                curvature,
+                1. / radius
+            );
+        }
+    }
+
+    #[test]
+    fn orientation_is_continuous() {
+        let spline = circle(10., 10., 18, 15.);
+        let angle = |a: Quat, b: Quat| a.angle_between(b).to_degrees();
+        for (i, &distance) in spline.distances().iter().enumerate() {
+            let at = spline.evaluate(Meters(distance)).unwrap().1;
+            assert!(angle(at, spline.points()[i].1) < 0.1, "point {}", i);
+            // the same orientation approached from either side of the point
+            if i > 0 && i < spline.points().len() - 1 {
+                let before = spline.evaluate(Meters(distance - 1e-3)).unwrap().1;
+                let after = spline.evaluate(Meters(distance + 1e-3)).unwrap().1;
+                assert!(angle(before, after) < 0.1, "point {}", i);
+            }
+        }
+        // no flips or jumps between the points either
+        let steps = 400;
+        let step = spline.length().0 / steps as f32;
+        let orientations = (0..=steps)
+            .map(|i| spline.evaluate(Meters(i as f32 * step)).unwrap().1)
+            .collect::<Vec<_>>();
+        let turns = orientations
+            .windows(2)
+            .map(|pair| angle(pair[0], pair[1]))
+            .collect::<Vec<_>>();
+        let mean = turns.iter().sum::<f32>() / turns.len() as f32;
+        for (i, &turn) in turns.iter().enumerate() {
+            assert!(turn < 1.5 * mean, "step {}: {} against {}", i, turn, mean);
+        }
+    }
+
+    #[test]
+    fn evaluate_time_matches_evaluate() {
+        let spline = circle(10., 10., 18, 15.);
+        assert_eq!(spline.times().len(), spline.points().len());
+        for (&time, &distance) in spline.times().iter().zip(spline.distances()) {
+            let (pos, rot) = spline.evaluate_time(Seconds(time)).unwrap();
+            let (expected_pos, expected_rot) = spline.evaluate(Meters(distance)).unwrap();
+            assert!(pos.distance(expected_pos) < 1e-3, "{}: {}", time, pos);
+            assert!(rot.angle_between(expected_rot) < 1e-3, "{}: {}", time, rot);
+        }
+        // the ride is at 1 m/s, so time and distance agree between the points too
+        for i in 0..=100 {
+            let time = spline.times().last().unwrap() * i as f32 / 100.;
+            let (pos, _) = spline.evaluate_time(Seconds(time)).unwrap();
+            let (expected, _) = spline.evaluate(Meters(time)).unwrap();
+            assert!(
+                pos.distance(expected) < 0.01,
+                "{}: {} != {}",
+                time,
+                pos,
+                expected
+            );
+        }
+
+        // an edited spline has no times
+        let mut edited = spline.clone();
+        edited.points_mut()[0].0 += Vec3::Y;
+        assert!(edited.evaluate_time(Seconds(0.)).is_none());
+    }
+}
@@ -0,0 +1,468 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fvd::DT,
+    groups::GroupId,
+    transitions::{
+        ChangeMode, EditError, ElementTag, ForceLevels, FullTransition, SectionInfo, Transition,
+        TransitionFunction, Transitions,
+    },
+    units::G,
+};
+
+const RAMP_TIME: f32 = 0.5; // s spent easing into and out of an element
+const LOOP_TOP_G: f32 = 1.; // normal force at the top of loops and cobra rolls
+const MAX_ELEMENT_TIME: f32 = 20.; // s, longest half loop the calibration will search
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    Left,
+    Right,
+}
+
+impl Direction {
+    pub fn flipped(&self) -> Direction {
+        match self {
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+
+    fn sign(&self) -> f32 {
+        match self {
+            Direction::Left => -1.,
+            Direction::Right => 1.,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Template {
+    AirtimeHill {
+        target_g: f32,
+        duration: f32,
+    },
+    VerticalLoop {
+        entry_speed: f32, // m/s
+        max_g: f32,
+    },
+    ZeroGRoll {
+        duration: f32,
+        direction: Direction,
+    },
+    CobraRoll {
+        entry_speed: f32, // m/s
+        max_g: f32,
+        direction: Direction,
+    },
+    Helix {
+        normal_g: f32,
+        bank: f32, // deg
+        duration: f32,
+        direction: Direction,
+    },
+}
+
+impl Template {
+    pub const EXAMPLES: [Template; 5] = [
+        Template::AirtimeHill {
+            target_g: -0.5,
+            duration: 2.,
+        },
+        Template::VerticalLoop {
+            entry_speed: 25.,
+            max_g: 4.,
+        },
+        Template::ZeroGRoll {
+            duration: 2.5,
+            direction: Direction::Right,
+        },
+        Template::CobraRoll {
+            entry_speed: 25.,
+            max_g: 4.,
+            direction: Direction::Right,
+        },
+        Template::Helix {
+            normal_g: 3.,
+            bank: 70.,
+            duration: 4.,
+            direction: Direction::Left,
+        },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Template::AirtimeHill { .. } => "Airtime Hill",
+            Template::VerticalLoop { .. } => "Vertical Loop",
+            Template::ZeroGRoll { .. } => "Zero-G Roll",
+            Template::CobraRoll { .. } => "Cobra Roll",
+            Template::Helix { .. } => "Helix",
+        }
+    }
+
+    // the same element turning or rolling the other way
+    pub fn mirrored(&self) -> Template {
+        let mut template = *self;
+        match &mut template {
+            Template::ZeroGRoll { direction, .. }
+            | Template::CobraRoll { direction, .. }
+            | Template::Helix { direction, .. } => *direction = direction.flipped(),
+            Template::AirtimeHill { .. } | Template::VerticalLoop { .. } => {}
+        }
+        template
+    }
+
+    pub fn tag(&self) -> ElementTag {
+        match self {
+            Template::AirtimeHill { .. } => ElementTag::AirtimeHill,
+            Template::VerticalLoop { .. } | Template::CobraRoll { .. } => ElementTag::Loop,
+            Template::ZeroGRoll { .. } => ElementTag::ZeroGRoll,
+            Template::Helix { .. } => ElementTag::Helix,
+        }
+    }
+
+    // durations must be positive, speeds too and everything else finite
+    pub fn check(&self) -> Result<(), EditError> {
+        let (duration, others) = match *self {
+            Template::AirtimeHill { target_g, duration } => (Some(duration), vec![target_g]),
+            Template::VerticalLoop { entry_speed, max_g }
+            | Template::CobraRoll {
+                entry_speed, max_g, ..
+            } => {
+                if entry_speed <= 0. {
+                    return Err(EditError::Infeasible);
+                }
+                (None, vec![entry_speed, max_g])
+            }
+            Template::ZeroGRoll { duration, .. } => (Some(duration), vec![]),
+            Template::Helix {
+                normal_g,
+                bank,
+                duration,
+                ..
+            } => (Some(duration), vec![normal_g, bank]),
+        };
+        match duration {
+            Some(duration) if !(duration.is_finite() && duration > 0.) => {
+                Err(EditError::InvalidLength(duration))
+            }
+            _ if others.iter().any(|value| !value.is_finite()) => Err(EditError::Infeasible),
+            _ => Ok(()),
+        }
+    }
+
+    // incoming is the level the element starts from, Infeasible if the parameters cannot
+    // make the element (e.g. a loop too slow to reach the top)
+    pub fn generate(&self, incoming: ForceLevels) -> Result<Vec<FullTransition>, EditError> {
+        self.check()?;
+        let sections = match *self {
+            Template::AirtimeHill { target_g, duration } => vec![
+                section(duration * 0.25, to(target_g), to(0.), to(0.)),
+                section(duration * 0.5, hold(), hold(), hold()),
+                section(duration * 0.25, to(1.), hold(), hold()),
+            ],
+            Template::VerticalLoop { entry_speed, max_g } => {
+                let (climb, descent) = calibrate_loop(entry_speed, incoming.vert.0, max_g)
+                    .ok_or(EditError::Infeasible)?;
+                vec![
+                    section(RAMP_TIME, to(max_g), to(0.), to(0.)),
+                    section(climb, to(LOOP_TOP_G), hold(), hold()),
+                    section(descent, to(max_g), hold(), hold()),
+                    section(RAMP_TIME, to(1.), hold(), hold()),
+                ]
+            }
+            Template::ZeroGRoll {
+                duration,
+                direction,
+            } => vec![
+                section(RAMP_TIME, to(0.), to(0.), to(0.)),
+                section(
+                    duration,
+                    hold(),
+                    hold(),
+                    roll_by(360. * direction.sign(), duration),
+                ),
+                section(RAMP_TIME, to(1.), hold(), hold()),
+            ],
+            Template::CobraRoll {
+                entry_speed,
+                max_g,
+                direction,
+            } => {
+                // approximated as a loop with opposite half rolls through the climb and the
+                // descent, which turns it around to the side, tune the result in the editor
+                let (climb, descent) = calibrate_loop(entry_speed, incoming.vert.0, max_g)
+                    .ok_or(EditError::Infeasible)?;
+                let half_roll = |duration: f32, offset: f32, sign: f32| Transition {
+                    offset,
+                    duration: Some(duration / 2.),
+                    ..roll_by(180. * sign, duration / 2.)
+                };
+                vec![
+                    section(RAMP_TIME, to(max_g), to(0.), to(0.)),
+                    section(
+                        climb,
+                        to(LOOP_TOP_G),
+                        hold(),
+                        half_roll(climb, climb / 4., direction.sign()),
+                    ),
+                    section(
+                        descent,
+                        to(max_g),
+                        hold(),
+                        half_roll(descent, descent / 4., -direction.sign()),
+                    ),
+                    section(RAMP_TIME, to(1.), hold(), hold()),
+                ]
+            }
+            Template::Helix {
+                normal_g,
+                bank,
+                duration,
+                direction,
+            } => {
+                let bank = bank * direction.sign();
+                vec![
+                    section(
+                        RAMP_TIME * 2.,
+                        to(normal_g),
+                        to(0.),
+                        roll_by(bank, RAMP_TIME * 2.),
+                    ),
+                    section(duration, hold(), hold(), hold()),
+                    section(
+                        RAMP_TIME * 2.,
+                        to(1.),
+                        hold(),
+                        roll_by(-bank, RAMP_TIME * 2.),
+                    ),
+                ]
+            }
+        };
+        // anything the calibration could not make sensible
+        if sections.iter().any(|section| {
+            !(section.length.is_finite() && section.length > 0.)
+                || [&section.vert, &section.lat, &section.roll]
+                    .iter()
+                    .any(|transition| !transition.change.is_finite())
+        }) {
+            return Err(EditError::Infeasible);
+        }
+        let tag = self.tag();
+        Ok(sections
+            .into_iter()
+            .map(|section| {
+                section.with_info(SectionInfo {
+                    name: Some(self.name().to_string()),
+                    tag: Some(tag),
+                    ..SectionInfo::default()
+                })
+            })
+            .collect())
+    }
+}
+
+impl Transitions {
+    // the inserted sections are grouped so they can be regenerated from the template
+    pub fn insert_template(
+        &mut self,
+        idx: usize,
+        template: &Template,
+    ) -> Result<GroupId, EditError> {
+        if idx > self.sections().len() {
+            return Err(EditError::IndexOutOfRange(idx));
+        }
+        if let Some(id) = self.group_at(idx, None) {
+            return Err(EditError::GroupOverlap(id));
+        }
+        let sections = template.generate(self.start_values(idx))?;
+        let len = sections.len();
+        let end = self.start_values(idx + 1);
+        self.sections_mut().splice(idx..idx, sections);
+        self.restore_end(idx + len, end);
+        let id = self.group(idx..idx + len, template.name())?;
+        self.groups_mut()
+            .iter_mut()
+            .find(|group| group.id == id)
+            .expect("group was just created")
+            .template = Some(*template);
+        Ok(id)
+    }
+}
+
+fn section(length: f32, vert: Transition, lat: Transition, roll: Transition) -> FullTransition {
+    FullTransition::new(vert, lat, roll, length, None)
+}
+
+fn to(value: f32) -> Transition {
+    Transition {
+        mode: ChangeMode::Absolute,
+        ..Transition::new(TransitionFunction::Cubic, value)
+    }
+}
+
+fn hold() -> Transition {
+    Transition::new(TransitionFunction::Linear, 0.)
+}
+
+// a plateau in roll rate that turns the track through angle degrees
+fn roll_by(angle: f32, duration: f32) -> Transition {
+    let function = TransitionFunction::PLATEAU;
+    let samples = 100;
+    let mean = (0..samples)
+        .map(|i| function.interpolate((i as f32 + 0.5) / samples as f32))
+        .sum::<f32>()
+        / samples as f32;
+    Transition::new(function, angle / (mean * duration))
+}
+
+// lengths of the climb (to the top) and descent (back to level) sections of a loop
+fn calibrate_loop(entry_speed: f32, incoming_g: f32, max_g: f32) -> Option<(f32, f32)> {
+    let cubic = |from: f32, to: f32| move |t: f32| from + (to - from) * ezing::cubic_inout(t);
+
+    let (pitch, speed) = pitch_after(0., entry_speed, RAMP_TIME, cubic(incoming_g, max_g))?;
+    let climb = solve_duration(pitch, speed, std::f32::consts::PI, cubic(max_g, LOOP_TOP_G))?;
+    let (pitch, speed) = pitch_after(pitch, speed, climb, cubic(max_g, LOOP_TOP_G))?;
+    let descent = solve_duration(
+        pitch,
+        speed,
+        std::f32::consts::TAU,
+        cubic(LOOP_TOP_G, max_g),
+    )?;
+    Some((climb, descent))
+}
+
+// bisects for the time a normal force profile takes to pitch the track up to target
+fn solve_duration(
+    pitch: f32,
+    speed: f32,
+    target: f32,
+    normal: impl Fn(f32) -> f32 + Copy,
+) -> Option<f32> {
+    let reaches =
+        |duration| pitch_after(pitch, speed, duration, normal).is_some_and(|(p, _)| p >= target);
+    if !reaches(MAX_ELEMENT_TIME) {
+        return None;
+    }
+    let (mut lo, mut hi) = (0., MAX_ELEMENT_TIME);
+    for _ in 0..24 {
+        let mid = (lo + hi) / 2.;
+        if reaches(mid) {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    Some(hi)
+}
+
+// integrates pitch and speed in the vertical plane, normal is in g over t in [0, 1]
+fn pitch_after(
+    mut pitch: f32,
+    mut speed: f32,
+    duration: f32,
+    normal: impl Fn(f32) -> f32,
+) -> Option<(f32, f32)> {
+    let mut time = 0.;
+    while time < duration {
+        pitch += (normal(time / duration) - pitch.cos()) * G / speed * DT;
+        let squared = speed * speed - 2. * G * pitch.sin() * speed * DT;
+        if squared <= 0. {
+            return None;
+        }
+        speed = squared.sqrt();
+        time += DT;
+    }
+    Some((pitch, speed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::{DegPerSec, GForce};
+
+    fn inserting(template: Template) -> Result<GroupId, EditError> {
+        let mut transitions = Transitions::new(GForce(1.), GForce(0.), DegPerSec(0.));
+        let before = transitions.sections().to_vec();
+        let result = transitions.insert_template(1, &template);
+        if result.is_err() {
+            assert_eq!(transitions.sections(), before);
+            assert!(transitions.groups().is_empty());
+        }
+        result
+    }
+
+    #[test]
+    fn examples_build() {
+        for template in Template::EXAMPLES {
+            assert!(inserting(template).is_ok(), "{}", template.name());
+            assert!(
+                inserting(template.mirrored()).is_ok(),
+                "{}",
+                template.name()
+            );
+        }
+    }
+
+    #[test]
+    fn bad_parameters_are_rejected() {
+        let roll = |duration| Template::ZeroGRoll {
+            duration,
+            direction: Direction::Left,
+        };
+        assert_eq!(inserting(roll(0.)), Err(EditError::InvalidLength(0.)));
+        assert!(matches!(
+            inserting(roll(f32::NAN)),
+            Err(EditError::InvalidLength(_))
+        ));
+        assert_eq!(
+            inserting(Template::AirtimeHill {
+                target_g: -0.5,
+                duration: -2.
+            }),
+            Err(EditError::InvalidLength(-2.))
+        );
+        assert_eq!(
+            inserting(Template::AirtimeHill {
+                target_g: f32::INFINITY,
+                duration: 2.
+            }),
+            Err(EditError::Infeasible)
+        );
+        assert_eq!(
+            inserting(Template::VerticalLoop {
+                entry_speed: 0.,
+                max_g: 4.
+            }),
+            Err(EditError::Infeasible)
+        );
+        assert_eq!(
+            inserting(Template::Helix {
+                normal_g: 3.,
+                bank: f32::NAN,
+                duration: 4.,
+                direction: Direction::Left,
+            }),
+            Err(EditError::Infeasible)
+        );
+    }
+
+    #[test]
+    fn bad_parameters_keep_the_group_as_it_was() {
+        let mut transitions = Transitions::new(GForce(1.), GForce(0.), DegPerSec(0.));
+        let id = transitions
+            .insert_template(1, &Template::EXAMPLES[2])
+            .unwrap();
+        let before = transitions.clone();
+        let bad = Template::ZeroGRoll {
+            duration: 0.,
+            direction: Direction::Right,
+        };
+        assert_eq!(
+            transitions.set_group_template(id, bad),
+            Err(EditError::InvalidLength(0.))
+        );
+        assert_eq!(transitions.sections(), before.sections());
+        assert_eq!(transitions.groups(), before.groups());
+    }
+}
@@ -1,17 +1,141 @@
-#[derive(Clone, Debug)]
+use std::{cell::OnceCell, fmt, ops::Range};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    expression::Expression,
+    groups::{Group, GroupId},
+    keyframes::Keyframes,
+    params::Bindings,
+    units::{DegPerSec, GForce, MetersPerSec, Seconds},
+};
+
+const LEVEL_TOLERANCE: f32 = 1e-4; // g or deg/s, smaller differences are rounding
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Transitions {
-    pub transitions: Vec<FullTransition>,
-    pub vert_start: f32,
-    pub lat_start: f32,
-    pub roll_start: f32,
+    transitions: Vec<FullTransition>,
+    vert_start: f32,
+    lat_start: f32,
+    roll_start: f32,
+    #[serde(default)]
+    groups: Vec<Group>,
+    #[serde(skip)]
+    table: OnceCell<Table>, // rebuilt lazily after any mutable access
 }
+
+#[derive(Clone, Debug)]
+struct TableEntry {
+    start_time: f32,
+    end_time: f32,
+    base: (f32, f32, f32), // accumulated vert, lat, roll at start_time
+    vert: Transition,      // resolved against base, so always relative
+    lat: Transition,
+    roll: Transition,
+    length: f32,
+    speed: Option<f32>,
+}
+
+#[derive(Clone, Debug)]
+struct Table {
+    entries: Vec<TableEntry>,
+    end_values: (f32, f32, f32),
+    length: f32,
+}
+
+// the forces at one moment of the ride
 #[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ForceSample {
+    pub section: usize, // active section, the last one once past the end
+    pub vert: GForce,
+    pub lat: GForce,
+    pub roll_rate: DegPerSec,
+    pub speed: Option<MetersPerSec>, // when the section fixes it
+}
+
+// the forces where a section starts
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ForceLevels {
+    pub vert: GForce,
+    pub lat: GForce,
+    pub roll_rate: DegPerSec,
+}
+
+impl ForceLevels {
+    fn from_values((vert, lat, roll_rate): (f32, f32, f32)) -> Self {
+        Self {
+            vert: GForce(vert),
+            lat: GForce(lat),
+            roll_rate: DegPerSec(roll_rate),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct FullTransition {
     pub vert: Transition,
     pub lat: Transition,
     pub roll: Transition,
-    pub length: f32,
-    pub speed: Option<f32>,
+    pub length: f32,        // s
+    pub speed: Option<f32>, // m/s
+    #[serde(default)]
+    pub info: SectionInfo,
+    #[serde(default)]
+    pub group: Option<GroupId>,
+    #[serde(default)]
+    pub bindings: Bindings,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SectionInfo {
+    pub name: Option<String>,
+    pub colour: Option<[u8; 3]>, // srgb
+    pub notes: String,
+    pub tag: Option<ElementTag>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ElementTag {
+    Drop,
+    AirtimeHill,
+    Turn,
+    Overbank,
+    Loop,
+    ZeroGRoll,
+    Helix,
+    Launch,
+    BrakeRun,
+    Other,
+}
+
+impl ElementTag {
+    pub const ALL: [ElementTag; 10] = [
+        ElementTag::Drop,
+        ElementTag::AirtimeHill,
+        ElementTag::Turn,
+        ElementTag::Overbank,
+        ElementTag::Loop,
+        ElementTag::ZeroGRoll,
+        ElementTag::Helix,
+        ElementTag::Launch,
+        ElementTag::BrakeRun,
+        ElementTag::Other,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ElementTag::Drop => "Drop",
+            ElementTag::AirtimeHill => "Airtime Hill",
+            ElementTag::Turn => "Turn",
+            ElementTag::Overbank => "Overbank",
+            ElementTag::Loop => "Loop",
+            ElementTag::ZeroGRoll => "Zero-G Roll",
+            ElementTag::Helix => "Helix",
+            ElementTag::Launch => "Launch",
+            ElementTag::BrakeRun => "Brake Run",
+            ElementTag::Other => "Other",
+        }
+    }
 }
 
 impl FullTransition {
@@ -28,6 +152,32 @@ impl FullTransition {
             roll,
             length,
             speed,
+            info: SectionInfo::default(),
+            group: None,
+            bindings: Bindings::default(),
+        }
+    }
+
+    pub fn with_info(mut self, info: SectionInfo) -> Self {
+        self.info = info;
+        self
+    }
+
+    pub fn with_group(mut self, group: Option<GroupId>) -> Self {
+        self.group = group;
+        self
+    }
+
+    // the name if there is one, otherwise the position in the layout; sections are numbered
+    // from 1 wherever they are shown, so messages and the section picker match this
+    pub fn label(&self, idx: usize) -> String {
+        let name = match &self.info.name {
+            Some(name) if !name.is_empty() => name.clone(),
+            _ => format!("Section {}", idx + 1),
+        };
+        match self.info.tag {
+            Some(tag) => format!("{} ({})", name, tag.name()),
+            None => name,
         }
     }
 }
@@ -36,9 +186,9 @@ impl Default for FullTransition {
     fn default() -> Self {
         let default = Transition::new(TransitionFunction::Cubic, 0.);
         Self::new(
+            default.clone(),
             default,
-            default,
-            Transition::new(TransitionFunction::Plateau, 0.),
+            Transition::new(TransitionFunction::PLATEAU, 0.),
             1.,
             None,
         )
@@ -46,65 +196,516 @@ impl Default for FullTransition {
 }
 
 impl Transitions {
-    pub fn new(vert_start: f32, lat_start: f32, roll_start: f32) -> Self {
+    pub fn new(vert_start: GForce, lat_start: GForce, roll_start: DegPerSec) -> Self {
         Self {
             transitions: vec![FullTransition::default()],
-            vert_start,
-            lat_start,
-            roll_start,
+            vert_start: vert_start.0,
+            lat_start: lat_start.0,
+            roll_start: roll_start.0,
+            groups: Vec::new(),
+            table: OnceCell::new(),
         }
     }
 
-    pub fn interpolate(&self, time: f32) -> Option<(f32, f32, f32, Option<f32>)> {
-        let mut vert_value = self.vert_start;
-        let mut lat_value = self.lat_start;
-        let mut roll_value = self.roll_start;
-        let mut fixed_speed = None;
-        if time < 0. || time > self.length() {
-            return None;
-        }
+    pub fn sections(&self) -> &[FullTransition] {
+        &self.transitions
+    }
+    pub fn sections_mut(&mut self) -> &mut Vec<FullTransition> {
+        self.table.take();
+        &mut self.transitions
+    }
+    pub fn start(&self) -> ForceLevels {
+        ForceLevels::from_values((self.vert_start, self.lat_start, self.roll_start))
+    }
+    pub fn set_start(&mut self, start: ForceLevels) {
+        self.table.take();
+        self.vert_start = start.vert.0;
+        self.lat_start = start.lat.0;
+        self.roll_start = start.roll_rate.0;
+    }
+    pub fn groups(&self) -> &[Group] {
+        &self.groups
+    }
+    // group metadata doesn't affect the force table, so this leaves it cached
+    pub fn groups_mut(&mut self) -> &mut Vec<Group> {
+        &mut self.groups
+    }
 
-        {
+    fn table(&self) -> &Table {
+        self.table.get_or_init(|| {
+            let mut entries = Vec::with_capacity(self.transitions.len());
+            let mut values = (self.vert_start, self.lat_start, self.roll_start);
             let mut time_so_far = 0.;
-
             for transition in &self.transitions {
-                fixed_speed = transition.speed;
-
-                if time_so_far <= time && time <= time_so_far + transition.length {
-                    vert_value += transition
-                        .vert
-                        .interpolate((time - time_so_far) / transition.length);
-                    lat_value += transition
-                        .lat
-                        .interpolate((time - time_so_far) / transition.length);
-                    roll_value += transition
-                        .roll
-                        .interpolate((time - time_so_far) / transition.length);
-                    break;
-                }
-                vert_value += transition.vert.end_value();
-                lat_value += transition.lat.end_value();
-                roll_value += transition.roll.end_value();
+                let vert = transition.vert.resolve(values.0);
+                let lat = transition.lat.resolve(values.1);
+                let roll = transition.roll.resolve(values.2);
+                let end = (vert.end_value(), lat.end_value(), roll.end_value());
+                entries.push(TableEntry {
+                    start_time: time_so_far,
+                    end_time: time_so_far + transition.length,
+                    base: values,
+                    vert,
+                    lat,
+                    roll,
+                    length: transition.length,
+                    speed: transition.speed,
+                });
+                values.0 += end.0;
+                values.1 += end.1;
+                values.2 += end.2;
                 time_so_far += transition.length;
             }
+            Table {
+                entries,
+                end_values: values,
+                length: time_so_far,
+            }
+        })
+    }
+
+    pub fn interpolate(&self, time: Seconds) -> Option<ForceSample> {
+        let Seconds(time) = time;
+        let table = self.table();
+        if time < 0. || time > table.length {
+            return None;
         }
 
-        Some((vert_value, lat_value, roll_value, fixed_speed))
+        let idx = table.entries.partition_point(|e| e.end_time < time);
+        match table.entries.get(idx) {
+            Some(entry) => {
+                let local_time = time - entry.start_time;
+                Some(ForceSample {
+                    section: idx,
+                    vert: GForce(entry.base.0 + entry.vert.sample(local_time, entry.length)),
+                    lat: GForce(entry.base.1 + entry.lat.sample(local_time, entry.length)),
+                    roll_rate: DegPerSec(
+                        entry.base.2 + entry.roll.sample(local_time, entry.length),
+                    ),
+                    speed: entry.speed.map(MetersPerSec),
+                })
+            }
+            None => {
+                let (vert, lat, roll_rate) = table.end_values;
+                Some(ForceSample {
+                    section: table.entries.len().saturating_sub(1),
+                    vert: GForce(vert),
+                    lat: GForce(lat),
+                    roll_rate: DegPerSec(roll_rate),
+                    speed: table.entries.last().and_then(|e| e.speed).map(MetersPerSec),
+                })
+            }
+        }
     }
-    pub fn length(&self) -> f32 {
-        self.transitions.iter().map(|v| v.length).sum::<f32>()
+    pub fn length(&self) -> Seconds {
+        Seconds(self.table().length)
+    }
+    pub fn start_values(&self, idx: usize) -> ForceLevels {
+        let table = self.table();
+        ForceLevels::from_values(
+            table
+                .entries
+                .get(idx)
+                .map_or(table.end_values, |entry| entry.base),
+        )
     }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EditError {
+    IndexOutOfRange(usize),
+    InvalidLength(f32),
+    LastSection, // there must always be at least one section
+    Infeasible,  // a template cannot be built from its parameters
+    UnknownGroup(GroupId),
+    GroupOverlap(GroupId), // groups cannot share or interleave sections
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditError::IndexOutOfRange(idx) => write!(f, "no section at index {}", idx),
+            EditError::InvalidLength(length) => {
+                write!(f, "section length must be positive, got {}", length)
+            }
+            EditError::LastSection => write!(f, "cannot remove the only section"),
+            EditError::Infeasible => write!(f, "element cannot be built from these parameters"),
+            EditError::UnknownGroup(id) => write!(f, "no group with id {}", id),
+            EditError::GroupOverlap(id) => write!(f, "sections belong to group {}", id),
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+// Edits keep every section after the edited range at the level it had before: the first
+// untouched section absorbs the difference by ending where it used to.
+impl Transitions {
+    pub fn insert(&mut self, idx: usize, section: FullTransition) -> Result<(), EditError> {
+        if idx > self.transitions.len() {
+            return Err(EditError::IndexOutOfRange(idx));
+        }
+        if section.length.is_nan() || section.length <= 0. {
+            return Err(EditError::InvalidLength(section.length));
+        }
+        let end = self.start_values(idx + 1);
+        let group = self.group_at(idx, section.group);
+        self.detach_template(group);
+        self.sections_mut().insert(idx, section.with_group(group));
+        self.restore_end(idx + 1, end);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, idx: usize) -> Result<FullTransition, EditError> {
+        if idx >= self.transitions.len() {
+            return Err(EditError::IndexOutOfRange(idx));
+        }
+        if self.transitions.len() == 1 {
+            return Err(EditError::LastSection);
+        }
+        let end = self.start_values(idx + 2);
+        let section = self.sections_mut().remove(idx);
+        self.prune_groups();
+        self.restore_end(idx, end);
+        Ok(section)
+    }
+
+    pub fn duplicate(&mut self, idx: usize) -> Result<(), EditError> {
+        let section = self
+            .transitions
+            .get(idx)
+            .cloned()
+            .ok_or(EditError::IndexOutOfRange(idx))?;
+        self.insert(idx + 1, section)
+    }
+
+    // the ride is unchanged, each half keeps its own piece of every curve
+    pub fn split(&mut self, idx: usize, time: Seconds) -> Result<(), EditError> {
+        let Seconds(time) = time;
+        let section = self
+            .transitions
+            .get(idx)
+            .cloned()
+            .ok_or(EditError::IndexOutOfRange(idx))?;
+        if !(time > 0. && time < section.length) {
+            return Err(EditError::InvalidLength(time));
+        }
+        let incoming = self.start_values(idx);
+        let (vert_a, vert_b) = section
+            .vert
+            .resolve(incoming.vert.0)
+            .split(time, section.length);
+        let (lat_a, lat_b) = section
+            .lat
+            .resolve(incoming.lat.0)
+            .split(time, section.length);
+        let (roll_a, roll_b) = section
+            .roll
+            .resolve(incoming.roll_rate.0)
+            .split(time, section.length);
+
+        let sections = self.sections_mut();
+        sections[idx] = FullTransition::new(vert_a, lat_a, roll_a, time, section.speed)
+            .with_info(section.info.clone())
+            .with_group(section.group);
+        sections.insert(
+            idx + 1,
+            FullTransition::new(vert_b, lat_b, roll_b, section.length - time, section.speed)
+                .with_info(section.info)
+                .with_group(section.group),
+        );
+        Ok(())
+    }
+
+    // swaps left and right, lateral forces and roll change sign
+    pub fn mirror(&mut self, range: Range<usize>) -> Result<(), EditError> {
+        self.reflect(range, |section| {
+            section.lat = section.lat.reflect(0.);
+            section.roll = section.roll.reflect(0.);
+            section.bindings.lat = None;
+            section.bindings.roll = None;
+        })
+    }
+
+    // flips the normal force around 1 g, so a hill becomes a valley
+    pub fn invert(&mut self, range: Range<usize>) -> Result<(), EditError> {
+        self.reflect(range, |section| {
+            section.vert = section.vert.reflect(1.);
+            section.bindings.vert = None;
+        })
+    }
+
+    fn reflect(
+        &mut self,
+        range: Range<usize>,
+        reflect: impl Fn(&mut FullTransition),
+    ) -> Result<(), EditError> {
+        if range.is_empty() || range.end > self.transitions.len() {
+            return Err(EditError::IndexOutOfRange(range.end));
+        }
+        let end = self.start_values(range.end + 1);
+        for section in &mut self.sections_mut()[range.clone()] {
+            reflect(section);
+        }
+        self.restore_end(range.end, end);
+        Ok(())
+    }
+
+    // the merged section ramps straight between the outer levels of the pair
+    pub fn merge(&mut self, idx: usize) -> Result<(), EditError> {
+        if idx + 1 >= self.transitions.len() {
+            return Err(EditError::IndexOutOfRange(idx + 1));
+        }
+        let start = self.start_values(idx);
+        let end = self.start_values(idx + 2);
+        let first = &self.transitions[idx];
+        let second = &self.transitions[idx + 1];
+
+        let merge_axis = |a: &Transition, change: f32| Transition {
+            change,
+            offset: 0.,
+            duration: None,
+            mode: ChangeMode::Relative,
+            function: match &a.function {
+                TransitionFunction::Plateau { .. }
+                | TransitionFunction::Expression(_)
+                | TransitionFunction::Keyframes(_) => TransitionFunction::Cubic,
+                function => function.clone(),
+            },
+        };
+        let merged = FullTransition::new(
+            merge_axis(&first.vert, (end.vert - start.vert).0),
+            merge_axis(&first.lat, (end.lat - start.lat).0),
+            merge_axis(&first.roll, (end.roll_rate - start.roll_rate).0),
+            first.length + second.length,
+            first.speed,
+        )
+        .with_info(first.info.clone())
+        .with_group(first.group);
+
+        let sections = self.sections_mut();
+        sections[idx] = merged;
+        sections.remove(idx + 1);
+        self.prune_groups();
+        Ok(())
+    }
+
+    pub fn move_section(&mut self, from: usize, to: usize) -> Result<(), EditError> {
+        let len = self.transitions.len();
+        if from >= len {
+            return Err(EditError::IndexOutOfRange(from));
+        }
+        if to >= len {
+            return Err(EditError::IndexOutOfRange(to));
+        }
+        let after = from.max(to) + 1;
+        let end = self.start_values(after + 1);
+        let section = self.sections_mut().remove(from);
+        let group = self.group_at(to, section.group);
+        self.detach_template(group);
+        self.sections_mut().insert(to, section.with_group(group));
+        self.prune_groups();
+        self.restore_end(after, end);
+        Ok(())
+    }
+
+    // brings the end of section idx back to end, unbinding what it has to change
+    pub(crate) fn restore_end(&mut self, idx: usize, end: ForceLevels) {
+        if idx >= self.transitions.len() {
+            return;
+        }
+        let incoming = self.start_values(idx);
+        let current = self.start_values(idx + 1);
+        let section = &mut self.sections_mut()[idx];
+        for (transition, binding, incoming, target, current) in [
+            (
+                &mut section.vert,
+                &mut section.bindings.vert,
+                incoming.vert.0,
+                end.vert.0,
+                current.vert.0,
+            ),
+            (
+                &mut section.lat,
+                &mut section.bindings.lat,
+                incoming.lat.0,
+                end.lat.0,
+                current.lat.0,
+            ),
+            (
+                &mut section.roll,
+                &mut section.bindings.roll,
+                incoming.roll_rate.0,
+                end.roll_rate.0,
+                current.roll_rate.0,
+            ),
+        ] {
+            if (target - current).abs() > LEVEL_TOLERANCE {
+                transition.shift_end(incoming, target - current);
+                *binding = None;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Transition {
     pub function: TransitionFunction,
     pub change: f32,
+    #[serde(default)]
+    pub offset: f32, // s after the section starts
+    #[serde(default)]
+    pub duration: Option<f32>, // s, None runs to the end of the section
+    #[serde(default)]
+    pub mode: ChangeMode,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeMode {
+    #[default]
+    Relative, // change is added to the incoming value
+    Absolute, // change is the value to ramp to
 }
 
 impl Transition {
     pub fn new(function: TransitionFunction, change: f32) -> Transition {
-        Transition { function, change }
+        Transition {
+            function,
+            change,
+            offset: 0.,
+            duration: None,
+            mode: ChangeMode::Relative,
+        }
+    }
+    pub fn resolve(&self, incoming: f32) -> Transition {
+        match self.mode {
+            ChangeMode::Relative => self.clone(),
+            ChangeMode::Absolute => Transition {
+                change: self.change - incoming,
+                mode: ChangeMode::Relative,
+                ..self.clone()
+            },
+        }
+    }
+    // expects a resolved (relative) transition, time is in s from the section start; the
+    // halves follow the curve exactly, so a cut through it turns it into keyframes
+    pub fn split(&self, time: f32, section_length: f32) -> (Transition, Transition) {
+        let (start, duration) = self.window(section_length);
+        let end = start + duration;
+        let flat = Transition::new(self.function.clone(), 0.);
+        if time <= start {
+            return (
+                flat,
+                Transition {
+                    offset: start - time,
+                    duration: self.duration.map(|_| duration),
+                    ..self.clone()
+                },
+            );
+        }
+        if time >= end {
+            return (
+                Transition {
+                    duration: Some(duration),
+                    ..self.clone()
+                },
+                flat,
+            );
+        }
+        let cut = (time - start) / duration;
+        let (first, second) = match &self.function {
+            TransitionFunction::Expression(expression) => (
+                TransitionFunction::Expression(expression.remap(0., cut, 0.)),
+                TransitionFunction::Expression(expression.remap(cut, 1., expression.evaluate(cut))),
+            ),
+            function => {
+                let keyframes = function.to_keyframes();
+                (
+                    TransitionFunction::Keyframes(keyframes.remap(0., cut)),
+                    TransitionFunction::Keyframes(keyframes.remap(cut, 1.)),
+                )
+            }
+        };
+        (
+            Transition {
+                function: first,
+                change: self.change,
+                offset: start,
+                duration: None,
+                mode: ChangeMode::Relative,
+            },
+            Transition {
+                function: second,
+                change: self.change,
+                offset: 0.,
+                duration: self.duration.map(|_| end - time),
+                mode: ChangeMode::Relative,
+            },
+        )
+    }
+    // moves where the transition ends by delta and leaves where it starts; a curve that
+    // always returns to its start (e.g. a plateau) gets a ramp to the new end blended in
+    pub fn shift_end(&mut self, incoming: f32, delta: f32) {
+        let scale = self.function.end_scale();
+        if scale != 0. {
+            // in either mode the end moves by scale for every unit of change
+            self.change += delta / scale;
+            return;
+        }
+        let resolved = self.resolve(incoming);
+        let keyframes = if resolved.change == 0. {
+            Keyframes::default()
+        } else {
+            let curve = resolved.function.to_keyframes();
+            let ratio = resolved.change / delta;
+            Keyframes::from_curve(
+                |t| ratio * curve.evaluate(t) + TransitionFunction::Smoothstep.interpolate(t),
+                &resolved.function.joins(),
+            )
+        };
+        *self = Transition {
+            function: TransitionFunction::Keyframes(keyframes),
+            change: delta,
+            mode: ChangeMode::Relative,
+            ..resolved
+        };
+    }
+    // relative changes flip sign, absolute targets are reflected about the level
+    pub fn reflect(&self, about: f32) -> Transition {
+        let change = match self.mode {
+            ChangeMode::Relative => -self.change,
+            ChangeMode::Absolute => 2. * about - self.change,
+        };
+        Transition {
+            change,
+            ..self.clone()
+        }
+    }
+    pub fn set_mode(&mut self, mode: ChangeMode, incoming: f32) {
+        self.change = match (self.mode, mode) {
+            (ChangeMode::Relative, ChangeMode::Absolute) => incoming + self.change,
+            (ChangeMode::Absolute, ChangeMode::Relative) => self.change - incoming,
+            _ => self.change,
+        };
+        self.mode = mode;
+    }
+    pub fn window(&self, section_length: f32) -> (f32, f32) {
+        let start = self.offset.clamp(0., section_length);
+        let duration = self
+            .duration
+            .unwrap_or(section_length - start)
+            .clamp(0., section_length - start);
+        (start, duration)
+    }
+    pub fn sample(&self, time: f32, section_length: f32) -> f32 {
+        let (start, duration) = self.window(section_length);
+        if time < start {
+            0.
+        } else if time >= start + duration {
+            self.interpolate(1.)
+        } else {
+            self.interpolate((time - start) / duration)
+        }
     }
     pub fn interpolate(&self, time: f32) -> f32 {
         if self.change == 0. {
@@ -115,39 +716,407 @@ impl Transition {
         self.function.interpolate(t) * self.change
     }
     pub fn end_value(&self) -> f32 {
-        match self.function {
-            TransitionFunction::Cubic
-            | TransitionFunction::Quadratic
-            | TransitionFunction::Linear => self.change,
-            TransitionFunction::Plateau => 0.,
-            // _ => self.function.interpolate(1.) * self.change,
-        }
+        self.function.end_scale() * self.change
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum TransitionFunction {
     Linear,
     Quadratic,
     Cubic,
-    Plateau,
+    Quartic,
+    Quintic,
+    Sine,
+    Smoothstep,
+    Smootherstep,
+    Plateau {
+        ramp_in: f32,  // fraction of the section spent building up
+        ramp_out: f32, // fraction of the section spent releasing
+        ease_in: Easing,
+        ease_out: Easing,
+    },
+    Bezier {
+        x1: f32,
+        y1: f32,
+        x2: f32,
+        y2: f32,
+    }, // css-style cubic-bezier control points
+    Expression(Expression), // formula in t
+    Keyframes(Keyframes),
 }
 
 impl TransitionFunction {
+    pub const PLATEAU: TransitionFunction = TransitionFunction::Plateau {
+        ramp_in: 0.33,
+        ramp_out: 0.34,
+        ease_in: Easing::Sine,
+        ease_out: Easing::Sine,
+    };
+
+    pub const ALL: [TransitionFunction; 10] = [
+        TransitionFunction::Linear,
+        TransitionFunction::Quadratic,
+        TransitionFunction::Cubic,
+        TransitionFunction::Quartic,
+        TransitionFunction::Quintic,
+        TransitionFunction::Sine,
+        TransitionFunction::Smoothstep,
+        TransitionFunction::Smootherstep,
+        TransitionFunction::PLATEAU,
+        TransitionFunction::Bezier {
+            x1: 0.42,
+            y1: 0.,
+            x2: 0.58,
+            y2: 1.,
+        },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransitionFunction::Linear => "Linear",
+            TransitionFunction::Quadratic => "Quadratic",
+            TransitionFunction::Cubic => "Cubic",
+            TransitionFunction::Quartic => "Quartic",
+            TransitionFunction::Quintic => "Quintic",
+            TransitionFunction::Sine => "Sine",
+            TransitionFunction::Smoothstep => "Smoothstep",
+            TransitionFunction::Smootherstep => "Smootherstep",
+            TransitionFunction::Plateau { .. } => "Plateau",
+            TransitionFunction::Bezier { .. } => "Bezier",
+            TransitionFunction::Expression(_) => "Expression",
+            TransitionFunction::Keyframes(_) => "Keyframes",
+        }
+    }
+
+    // where the curve ends relative to the change, plateaus return to where they started
+    pub fn end_scale(&self) -> f32 {
+        match self {
+            TransitionFunction::Plateau { .. } => 0.,
+            TransitionFunction::Expression(expression) => expression.evaluate(1.),
+            TransitionFunction::Keyframes(keyframes) => keyframes.end_value(),
+            _ => 1.,
+        }
+    }
+
+    // the same curve as keyframes, e.g. to cut it in two
+    pub fn to_keyframes(&self) -> Keyframes {
+        match self {
+            TransitionFunction::Keyframes(keyframes) => keyframes.clone(),
+            function => Keyframes::from_curve(|t| function.interpolate(t), &function.joins()),
+        }
+    }
+
+    // where the pieces of the curve meet, e.g. the ends of a plateau's ramps
+    fn joins(&self) -> Vec<f32> {
+        match self {
+            TransitionFunction::Quadratic
+            | TransitionFunction::Cubic
+            | TransitionFunction::Quartic
+            | TransitionFunction::Quintic => vec![0.5],
+            TransitionFunction::Plateau {
+                ramp_in, ramp_out, ..
+            } => {
+                let (ramp_in, ramp_out) = plateau_ramps(*ramp_in, *ramp_out);
+                let hold_end = 1. - ramp_out;
+                vec![ramp_in / 2., ramp_in, hold_end, hold_end + ramp_out / 2.]
+            }
+            _ => Vec::new(),
+        }
+    }
+
     pub fn interpolate(&self, t: f32) -> f32 {
         let t = t.clamp(0., 1.);
         match self {
             TransitionFunction::Linear => ezing::linear(t),
             TransitionFunction::Quadratic => ezing::quad_inout(t),
             TransitionFunction::Cubic => ezing::cubic_inout(t),
-            TransitionFunction::Plateau => {
-                if (0.33..=0.66).contains(&t) {
+            TransitionFunction::Quartic => ezing::quart_inout(t),
+            TransitionFunction::Quintic => ezing::quint_inout(t),
+            TransitionFunction::Sine => ezing::sine_inout(t),
+            TransitionFunction::Smoothstep => t * t * (3. - 2. * t),
+            TransitionFunction::Smootherstep => t * t * t * (t * (6. * t - 15.) + 10.),
+            TransitionFunction::Plateau {
+                ramp_in,
+                ramp_out,
+                ease_in,
+                ease_out,
+            } => {
+                let (ramp_in, ramp_out) = plateau_ramps(*ramp_in, *ramp_out);
+                let hold_end = 1. - ramp_out;
+                if t < ramp_in {
+                    ease_in.interpolate(t / ramp_in)
+                } else if t <= hold_end {
                     1.
-                } else if t < 0.33 {
-                    ezing::sine_inout(t * (1. / 0.33))
                 } else {
-                    1. - ezing::sine_inout((t - 0.66) * (1. / 0.33))
+                    1. - ease_out.interpolate((t - hold_end) / ramp_out)
+                }
+            }
+            TransitionFunction::Bezier { x1, y1, x2, y2 } => {
+                let bezier = |a: f32, b: f32, s: f32| {
+                    3. * a * s * (1. - s) * (1. - s) + 3. * b * s * s * (1. - s) + s * s * s
+                };
+                let (x1, x2) = (x1.clamp(0., 1.), x2.clamp(0., 1.));
+
+                // x(s) is monotonic once the control points are inside [0, 1], so bisect for it
+                let (mut lo, mut hi) = (0., 1.);
+                for _ in 0..24 {
+                    let mid = (lo + hi) / 2.;
+                    if bezier(x1, x2, mid) < t {
+                        lo = mid;
+                    } else {
+                        hi = mid;
+                    }
                 }
+                bezier(*y1, *y2, (lo + hi) / 2.)
+            }
+            TransitionFunction::Expression(expression) => expression.evaluate(t),
+            TransitionFunction::Keyframes(keyframes) => keyframes.evaluate(t),
+        }
+    }
+}
+
+// the ramp fractions a plateau actually uses, always leaving room for both
+fn plateau_ramps(ramp_in: f32, ramp_out: f32) -> (f32, f32) {
+    let ramp_in = ramp_in.clamp(0.01, 0.99);
+    (ramp_in, ramp_out.clamp(0.01, 1. - ramp_in))
+}
+
+impl From<Easing> for TransitionFunction {
+    fn from(easing: Easing) -> Self {
+        match easing {
+            Easing::Linear => TransitionFunction::Linear,
+            Easing::Quadratic => TransitionFunction::Quadratic,
+            Easing::Cubic => TransitionFunction::Cubic,
+            Easing::Sine => TransitionFunction::Sine,
+            Easing::Smoothstep => TransitionFunction::Smoothstep,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Easing {
+    Linear,
+    Quadratic,
+    Cubic,
+    Sine,
+    Smoothstep,
+}
+
+impl Easing {
+    pub const ALL: [Easing; 5] = [
+        Easing::Linear,
+        Easing::Quadratic,
+        Easing::Cubic,
+        Easing::Sine,
+        Easing::Smoothstep,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Easing::Linear => "Linear",
+            Easing::Quadratic => "Quadratic",
+            Easing::Cubic => "Cubic",
+            Easing::Sine => "Sine",
+            Easing::Smoothstep => "Smoothstep",
+        }
+    }
+
+    pub fn interpolate(&self, t: f32) -> f32 {
+        let t = t.clamp(0., 1.);
+        match self {
+            Easing::Linear => ezing::linear(t),
+            Easing::Quadratic => ezing::quad_inout(t),
+            Easing::Cubic => ezing::cubic_inout(t),
+            Easing::Sine => ezing::sine_inout(t),
+            Easing::Smoothstep => t * t * (3. - 2. * t),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        keyframes::Keyframe,
+        templates::{Direction, Template},
+    };
+
+    fn section(vert: Transition, lat: Transition, roll: Transition) -> FullTransition {
+        FullTransition::new(vert, lat, roll, 1.5, None)
+    }
+
+    fn cubic(change: f32) -> Transition {
+        Transition::new(TransitionFunction::Cubic, change)
+    }
+
+    fn plateau(change: f32) -> Transition {
+        Transition::new(TransitionFunction::PLATEAU, change)
+    }
+
+    // ramps, plateaus and flat sections on every axis
+    fn layout() -> Transitions {
+        let mut transitions = Transitions::new(GForce(1.), GForce(0.), DegPerSec(0.));
+        *transitions.sections_mut() = vec![
+            section(cubic(1.), cubic(0.5), cubic(30.)),
+            section(plateau(-0.5), plateau(0.2), plateau(0.)),
+            FullTransition::default(),
+            section(cubic(-1.), cubic(-0.5), cubic(-30.)),
+            FullTransition::default(),
+        ];
+        transitions
+    }
+
+    fn assert_close(a: ForceLevels, b: ForceLevels, what: &str) {
+        let error = (a.vert - b.vert)
+            .0
+            .abs()
+            .max((a.lat - b.lat).0.abs())
+            .max((a.roll_rate - b.roll_rate).0.abs());
+        assert!(error < 1e-3, "{}: {:?} != {:?}", what, a, b);
+    }
+
+    // pairs of (index before, index after) of sections that must start at the same level
+    fn assert_levels_kept(before: &Transitions, after: &Transitions, pairs: &[(usize, usize)]) {
+        for &(old, new) in pairs {
+            assert_close(
+                before.start_values(old),
+                after.start_values(new),
+                &format!("section {} -> {}", old, new),
+            );
+        }
+    }
+
+    fn assert_same_ride(before: &Transitions, after: &Transitions) {
+        assert!((before.length().0 - after.length().0).abs() < 1e-4);
+        for i in 0..=(before.length().0 * 100.) as usize {
+            let time = Seconds(i as f32 / 100.);
+            let levels = |transitions: &Transitions| {
+                let sample = transitions.interpolate(time).unwrap();
+                ForceLevels {
+                    vert: sample.vert,
+                    lat: sample.lat,
+                    roll_rate: sample.roll_rate,
+                }
+            };
+            assert_close(levels(before), levels(after), &format!("at {}s", time.0));
+        }
+    }
+
+    #[test]
+    fn insert_before_a_plateau_keeps_later_levels() {
+        let mut transitions = Transitions::new(GForce(1.), GForce(0.), DegPerSec(0.));
+        transitions.sections_mut().push(FullTransition::default());
+        let before = transitions.clone();
+        let roll = FullTransition {
+            roll: cubic(50.),
+            ..FullTransition::default()
+        };
+        transitions.insert(0, roll).unwrap();
+        assert_eq!(transitions.start_values(1).roll_rate, DegPerSec(50.));
+        assert_levels_kept(&before, &transitions, &[(1, 2), (2, 3)]);
+    }
+
+    #[test]
+    fn edits_keep_later_levels() {
+        let before = layout();
+
+        let mut inserted = before.clone();
+        inserted
+            .insert(1, section(cubic(1.), cubic(0.3), cubic(50.)))
+            .unwrap();
+        assert_levels_kept(&before, &inserted, &[(2, 3), (3, 4), (4, 5), (5, 6)]);
+
+        let mut removed = before.clone();
+        removed.remove(0).unwrap();
+        assert_levels_kept(&before, &removed, &[(2, 1), (3, 2), (5, 4)]);
+
+        let mut moved = before.clone();
+        moved.move_section(0, 2).unwrap();
+        assert_levels_kept(&before, &moved, &[(4, 4), (5, 5)]);
+
+        let mut mirrored = before.clone();
+        mirrored.mirror(0..1).unwrap();
+        assert_levels_kept(&before, &mirrored, &[(2, 2), (3, 3), (5, 5)]);
+
+        let mut inverted = before.clone();
+        inverted.invert(0..1).unwrap();
+        assert_levels_kept(&before, &inverted, &[(2, 2), (3, 3), (5, 5)]);
+    }
+
+    #[test]
+    fn group_edits_keep_later_levels() {
+        let before = layout();
+        let mut grouped = before.clone();
+        let id = grouped.group(0..2, "element").unwrap();
+
+        let mut scaled = grouped.clone();
+        scaled.scale_group(id, 2.).unwrap();
+        assert_levels_kept(&before, &scaled, &[(3, 3), (4, 4), (5, 5)]);
+
+        let mut duplicated = grouped.clone();
+        duplicated.duplicate_group(id).unwrap();
+        assert_levels_kept(&before, &duplicated, &[(3, 5), (4, 6), (5, 7)]);
+
+        let mut templated = before.clone();
+        let id = templated
+            .insert_template(1, &Template::EXAMPLES[0])
+            .unwrap();
+        assert_levels_kept(&before, &templated, &[(2, 5), (3, 6), (5, 8)]);
+        let mut regenerated = templated.clone();
+        regenerated
+            .set_group_template(
+                id,
+                Template::ZeroGRoll {
+                    duration: 3.,
+                    direction: Direction::Left,
+                },
+            )
+            .unwrap();
+        assert_levels_kept(&templated, &regenerated, &[(5, 5), (6, 6), (8, 8)]);
+    }
+
+    #[test]
+    fn split_keeps_the_ride() {
+        let functions = [
+            TransitionFunction::PLATEAU,
+            TransitionFunction::Plateau {
+                ramp_in: 0.2,
+                ramp_out: 0.3,
+                ease_in: Easing::Linear,
+                ease_out: Easing::Quadratic,
+            },
+            TransitionFunction::Cubic,
+            TransitionFunction::Quintic,
+            TransitionFunction::Bezier {
+                x1: 0.1,
+                y1: 0.8,
+                x2: 0.6,
+                y2: 1.2,
+            },
+            TransitionFunction::Expression(Expression::parse("t^2 + sin(pi * t) / 4").unwrap()),
+            TransitionFunction::Keyframes(Keyframes::new(vec![
+                Keyframe::new(0.4, 1.5, 0.),
+                Keyframe::new(1., 1., 0.),
+            ])),
+        ];
+        for function in functions {
+            for time in [0.2, 0.5, 1.2, 2.5] {
+                let mut transitions = Transitions::new(GForce(1.), GForce(0.), DegPerSec(0.));
+                let mut vert = Transition::new(function.clone(), 1.);
+                vert.offset = 0.4;
+                vert.duration = Some(2.);
+                let mut lat = Transition::new(function.clone(), -0.5);
+                lat.mode = ChangeMode::Absolute;
+                let roll = Transition::new(function.clone(), 0.9);
+                *transitions.sections_mut() = vec![
+                    FullTransition::new(vert, lat, roll, 3., None),
+                    FullTransition::default(),
+                ];
+                let before = transitions.clone();
+                transitions.split(0, Seconds(time)).unwrap();
+                assert_eq!(transitions.sections().len(), 3);
+                assert_same_ride(&before, &transitions);
             }
         }
     }
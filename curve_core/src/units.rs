@@ -1,7 +1,19 @@
+use std::{
+    fmt,
+    ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign},
+};
+
 use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+pub const G: f32 = 9.806; // m/s^2
+
+const FEET_PER_METER: f32 = 3.280_84;
+const MPH_PER_MPS: f32 = 2.2369363;
+const KMH_PER_MPS: f32 = 3.6;
 
 pub fn m_to_ft(v: f32) -> f32 {
-    v * 3.2808399
+    v * FEET_PER_METER
 }
 
 pub fn m_to_ft_vec3(pos: Vec3) -> Vec3 {
@@ -9,7 +21,353 @@ pub fn m_to_ft_vec3(pos: Vec3) -> Vec3 {
 }
 
 pub fn mps_to_miph(velocity: f32) -> f32 {
-    velocity * 2.2369363
+    velocity * MPH_PER_MPS
+}
+
+// Quantities passed through the api carry their unit in the type. Stored fields (section
+// lengths, changes, settings) stay plain f32 with the unit noted beside them, so project
+// files and the editor widgets bound to them are unaffected.
+macro_rules! unit {
+    ($name:ident, $suffix:literal) => {
+        #[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+        #[serde(transparent)]
+        pub struct $name(pub f32);
+
+        impl Add for $name {
+            type Output = $name;
+            fn add(self, rhs: $name) -> $name {
+                $name(self.0 + rhs.0)
+            }
+        }
+
+        impl AddAssign for $name {
+            fn add_assign(&mut self, rhs: $name) {
+                self.0 += rhs.0;
+            }
+        }
+
+        impl Sub for $name {
+            type Output = $name;
+            fn sub(self, rhs: $name) -> $name {
+                $name(self.0 - rhs.0)
+            }
+        }
+
+        impl SubAssign for $name {
+            fn sub_assign(&mut self, rhs: $name) {
+                self.0 -= rhs.0;
+            }
+        }
+
+        impl Neg for $name {
+            type Output = $name;
+            fn neg(self) -> $name {
+                $name(-self.0)
+            }
+        }
+
+        impl Mul<f32> for $name {
+            type Output = $name;
+            fn mul(self, rhs: f32) -> $name {
+                $name(self.0 * rhs)
+            }
+        }
+
+        impl Div<f32> for $name {
+            type Output = $name;
+            fn div(self, rhs: f32) -> $name {
+                $name(self.0 / rhs)
+            }
+        }
+
+        // the ratio of two quantities of the same unit
+        impl Div for $name {
+            type Output = f32;
+            fn div(self, rhs: $name) -> f32 {
+                self.0 / rhs.0
+            }
+        }
+
+        // the precision is passed on to the number, {:.2} gives "1.50 m"
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match f.precision() {
+                    Some(precision) => write!(f, "{:.*} {}", precision, self.0, $suffix),
+                    None => write!(f, "{} {}", self.0, $suffix),
+                }
+            }
+        }
+    };
+}
+
+unit!(Meters, "m");
+unit!(Seconds, "s");
+unit!(GForce, "g");
+unit!(DegPerSec, "deg/s");
+unit!(MetersPerSec, "m/s");
+
+impl Meters {
+    pub fn from_feet(feet: f32) -> Self {
+        Meters(feet / FEET_PER_METER)
+    }
+    pub fn to_feet(self) -> f32 {
+        m_to_ft(self.0)
+    }
+}
+
+impl GForce {
+    // from an acceleration in m/s^2
+    pub fn from_accel(accel: f32) -> Self {
+        GForce(accel / G)
+    }
+    pub fn to_accel(self) -> f32 {
+        self.0 * G
+    }
+}
+
+impl DegPerSec {
+    pub fn from_radians(rate: f32) -> Self {
+        DegPerSec(rate.to_degrees())
+    }
+    // rad/s
+    pub fn to_radians(self) -> f32 {
+        self.0.to_radians()
+    }
+}
+
+impl MetersPerSec {
+    pub fn from_mph(mph: f32) -> Self {
+        MetersPerSec(mph / MPH_PER_MPS)
+    }
+    pub fn to_mph(self) -> f32 {
+        mps_to_miph(self.0)
+    }
+    pub fn from_kmh(kmh: f32) -> Self {
+        MetersPerSec(kmh / KMH_PER_MPS)
+    }
+    pub fn to_kmh(self) -> f32 {
+        self.0 * KMH_PER_MPS
+    }
+    pub fn from_feet_per_sec(fps: f32) -> Self {
+        MetersPerSec(fps / FEET_PER_METER)
+    }
+    pub fn to_feet_per_sec(self) -> f32 {
+        self.0 * FEET_PER_METER
+    }
+}
+
+impl Mul<Seconds> for MetersPerSec {
+    type Output = Meters;
+    fn mul(self, rhs: Seconds) -> Meters {
+        Meters(self.0 * rhs.0)
+    }
+}
+
+impl Mul<MetersPerSec> for Seconds {
+    type Output = Meters;
+    fn mul(self, rhs: MetersPerSec) -> Meters {
+        rhs * self
+    }
+}
+
+impl Div<Seconds> for Meters {
+    type Output = MetersPerSec;
+    fn div(self, rhs: Seconds) -> MetersPerSec {
+        MetersPerSec(self.0 / rhs.0)
+    }
+}
+
+impl Div<MetersPerSec> for Meters {
+    type Output = Seconds;
+    fn div(self, rhs: MetersPerSec) -> Seconds {
+        Seconds(self.0 / rhs.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LengthUnit {
+    Meters,
+    Feet,
+}
+
+impl LengthUnit {
+    pub const ALL: [LengthUnit; 2] = [LengthUnit::Meters, LengthUnit::Feet];
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            LengthUnit::Meters => "m",
+            LengthUnit::Feet => "ft",
+        }
+    }
+
+    pub fn from_meters(&self, length: Meters) -> f32 {
+        match self {
+            LengthUnit::Meters => length.0,
+            LengthUnit::Feet => length.to_feet(),
+        }
+    }
+
+    pub fn to_meters(&self, value: f32) -> Meters {
+        match self {
+            LengthUnit::Meters => Meters(value),
+            LengthUnit::Feet => Meters::from_feet(value),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpeedUnit {
+    MetersPerSec,
+    KilometersPerHour,
+    MilesPerHour,
+    FeetPerSec,
+}
+
+impl SpeedUnit {
+    pub const ALL: [SpeedUnit; 4] = [
+        SpeedUnit::MetersPerSec,
+        SpeedUnit::KilometersPerHour,
+        SpeedUnit::MilesPerHour,
+        SpeedUnit::FeetPerSec,
+    ];
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            SpeedUnit::MetersPerSec => "m/s",
+            SpeedUnit::KilometersPerHour => "km/h",
+            SpeedUnit::MilesPerHour => "mph",
+            SpeedUnit::FeetPerSec => "ft/s",
+        }
+    }
+
+    pub fn from_mps(&self, speed: MetersPerSec) -> f32 {
+        match self {
+            SpeedUnit::MetersPerSec => speed.0,
+            SpeedUnit::KilometersPerHour => speed.to_kmh(),
+            SpeedUnit::MilesPerHour => speed.to_mph(),
+            SpeedUnit::FeetPerSec => speed.to_feet_per_sec(),
+        }
+    }
+
+    pub fn to_mps(&self, value: f32) -> MetersPerSec {
+        match self {
+            SpeedUnit::MetersPerSec => MetersPerSec(value),
+            SpeedUnit::KilometersPerHour => MetersPerSec::from_kmh(value),
+            SpeedUnit::MilesPerHour => MetersPerSec::from_mph(value),
+            SpeedUnit::FeetPerSec => MetersPerSec::from_feet_per_sec(value),
+        }
+    }
 }
 
-pub const G: f32 = 9.806;
+// how lengths and speeds are entered and shown, designs are always stored in si units.
+// forces (g), times (s) and roll rates (deg/s) read the same in every system
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnitSystem {
+    pub length: LengthUnit,
+    pub speed: SpeedUnit,
+}
+
+impl Default for UnitSystem {
+    fn default() -> Self {
+        UnitSystem::METRIC
+    }
+}
+
+impl UnitSystem {
+    pub const METRIC: UnitSystem = UnitSystem {
+        length: LengthUnit::Meters,
+        speed: SpeedUnit::MetersPerSec,
+    };
+    pub const IMPERIAL: UnitSystem = UnitSystem {
+        length: LengthUnit::Feet,
+        speed: SpeedUnit::MilesPerHour,
+    };
+    pub const PRESETS: [UnitSystem; 2] = [UnitSystem::METRIC, UnitSystem::IMPERIAL];
+
+    // any other combination is a custom system
+    pub fn name(&self) -> &'static str {
+        match *self {
+            UnitSystem::METRIC => "Metric",
+            UnitSystem::IMPERIAL => "Imperial",
+            _ => "Custom",
+        }
+    }
+
+    pub fn format_length(&self, length: Meters) -> String {
+        format!(
+            "{:.1} {}",
+            self.length.from_meters(length),
+            self.length.suffix()
+        )
+    }
+
+    pub fn format_speed(&self, speed: MetersPerSec) -> String {
+        format!("{:.1} {}", self.speed.from_mps(speed), self.speed.suffix())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-5 * a.abs().max(b.abs()).max(1.)
+    }
+
+    #[test]
+    fn lengths_round_trip() {
+        for unit in LengthUnit::ALL {
+            for meters in [0., 1., -2.5, 123.456] {
+                let value = unit.from_meters(Meters(meters));
+                let back = unit.to_meters(value);
+                assert!(close(back.0, meters), "{:?}: {} != {}", unit, back, meters);
+            }
+        }
+        assert!(close(LengthUnit::Feet.from_meters(Meters(0.3048)), 1.));
+        assert_eq!(LengthUnit::Meters.from_meters(Meters(2.)), 2.);
+    }
+
+    #[test]
+    fn speeds_round_trip() {
+        for unit in SpeedUnit::ALL {
+            for mps in [0., 1., -2.5, 123.456] {
+                let value = unit.from_mps(MetersPerSec(mps));
+                let back = unit.to_mps(value);
+                assert!(close(back.0, mps), "{:?}: {} != {}", unit, back, mps);
+            }
+        }
+        // 100 km/h, 60 mph and 100 ft/s
+        let mps = |unit: SpeedUnit, value| unit.to_mps(value).0;
+        assert!(close(mps(SpeedUnit::KilometersPerHour, 100.), 27.777_779));
+        assert!(close(mps(SpeedUnit::MilesPerHour, 60.), 26.8224));
+        assert!(close(mps(SpeedUnit::FeetPerSec, 100.), 30.48));
+        assert_eq!(mps(SpeedUnit::MetersPerSec, 5.), 5.);
+    }
+
+    #[test]
+    fn presets() {
+        assert_eq!(UnitSystem::default(), UnitSystem::METRIC);
+        assert_eq!(UnitSystem::METRIC.name(), "Metric");
+        assert_eq!(UnitSystem::IMPERIAL.name(), "Imperial");
+        let custom = UnitSystem {
+            speed: SpeedUnit::KilometersPerHour,
+            ..UnitSystem::METRIC
+        };
+        assert_eq!(custom.name(), "Custom");
+
+        assert_eq!(UnitSystem::METRIC.format_length(Meters(30.48)), "30.5 m");
+        assert_eq!(
+            UnitSystem::IMPERIAL.format_length(Meters(30.48)),
+            "100.0 ft"
+        );
+        assert_eq!(
+            UnitSystem::METRIC.format_speed(MetersPerSec(26.8224)),
+            "26.8 m/s"
+        );
+        assert_eq!(
+            UnitSystem::IMPERIAL.format_speed(MetersPerSec(26.8224)),
+            "60.0 mph"
+        );
+        assert_eq!(custom.format_speed(MetersPerSec(10.)), "36.0 km/h");
+    }
+}
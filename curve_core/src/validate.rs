@@ -0,0 +1,405 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::transitions::{Transition, Transitions};
+
+const SAMPLES_PER_SECTION: usize = 20; // points checked against the force limits
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DesignLimits {
+    pub max_vert: f32, // g
+    pub min_vert: f32,
+    pub max_lat: f32,       // g either way
+    pub max_roll_rate: f32, // deg/s either way
+    pub min_section: f32,   // s
+}
+
+impl Default for DesignLimits {
+    fn default() -> Self {
+        Self {
+            max_vert: 6.,
+            min_vert: -1.5,
+            max_lat: 1.8,
+            max_roll_rate: 1000.,
+            min_section: 0.1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning, // simulates, but is outside the design limits
+    Error,   // the solver cannot produce a sensible track
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Length,
+    Speed,
+    Vert,
+    Lat,
+    Roll,
+}
+
+impl Field {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Length => "length",
+            Field::Speed => "speed",
+            Field::Vert => "normal",
+            Field::Lat => "lateral",
+            Field::Roll => "roll",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Issue {
+    NotFinite,
+    InvalidLength(f32), // zero or negative
+    InvalidSpeed(f32),
+    ShortSection { length: f32, min: f32 },
+    AboveLimit { value: f32, limit: f32 },
+    BelowLimit { value: f32, limit: f32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub section: Option<usize>, // index, None for the start of the track
+    pub field: Field,
+    pub issue: Issue,
+}
+
+impl Diagnostic {
+    pub fn new(section: Option<usize>, field: Field, issue: Issue) -> Self {
+        Self {
+            section,
+            field,
+            issue,
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self.issue {
+            Issue::NotFinite | Issue::InvalidLength(_) | Issue::InvalidSpeed(_) => Severity::Error,
+            Issue::ShortSection { .. } | Issue::AboveLimit { .. } | Issue::BelowLimit { .. } => {
+                Severity::Warning
+            }
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.section {
+            Some(idx) => write!(f, "section {} {}: ", idx + 1, self.field.name())?,
+            None => write!(f, "start {}: ", self.field.name())?,
+        }
+        match self.issue {
+            Issue::NotFinite => write!(f, "value is not a finite number"),
+            Issue::InvalidLength(length) => write!(f, "length {}s must be positive", length),
+            Issue::InvalidSpeed(speed) => write!(f, "speed {}m/s must be positive", speed),
+            Issue::ShortSection { length, min } => {
+                write!(f, "{}s is shorter than the {}s minimum", length, min)
+            }
+            Issue::AboveLimit { value, limit } => {
+                write!(f, "reaches {:.2}, above the limit of {}", value, limit)
+            }
+            Issue::BelowLimit { value, limit } => {
+                write!(f, "reaches {:.2}, below the limit of {}", value, limit)
+            }
+        }
+    }
+}
+
+impl Transitions {
+    // errors first, the force limits are only checked once every value is usable
+    pub fn validate(&self, limits: &DesignLimits) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        let start = self.start();
+        for (field, value) in [
+            (Field::Vert, start.vert.0),
+            (Field::Lat, start.lat.0),
+            (Field::Roll, start.roll_rate.0),
+        ] {
+            if !value.is_finite() {
+                diagnostics.push(Diagnostic::new(None, field, Issue::NotFinite));
+            }
+        }
+
+        for (idx, section) in self.sections().iter().enumerate() {
+            let section_idx = Some(idx);
+            if !section.length.is_finite() {
+                diagnostics.push(Diagnostic::new(
+                    section_idx,
+                    Field::Length,
+                    Issue::NotFinite,
+                ));
+            } else if section.length <= 0. {
+                diagnostics.push(Diagnostic::new(
+                    section_idx,
+                    Field::Length,
+                    Issue::InvalidLength(section.length),
+                ));
+            } else if section.length < limits.min_section {
+                diagnostics.push(Diagnostic::new(
+                    section_idx,
+                    Field::Length,
+                    Issue::ShortSection {
+                        length: section.length,
+                        min: limits.min_section,
+                    },
+                ));
+            }
+            match section.speed {
+                Some(speed) if !speed.is_finite() => {
+                    diagnostics.push(Diagnostic::new(section_idx, Field::Speed, Issue::NotFinite))
+                }
+                Some(speed) if speed <= 0. => diagnostics.push(Diagnostic::new(
+                    section_idx,
+                    Field::Speed,
+                    Issue::InvalidSpeed(speed),
+                )),
+                _ => {}
+            }
+            for (field, transition) in [
+                (Field::Vert, &section.vert),
+                (Field::Lat, &section.lat),
+                (Field::Roll, &section.roll),
+            ] {
+                if !timing_is_finite(transition) {
+                    diagnostics.push(Diagnostic::new(section_idx, field, Issue::NotFinite));
+                }
+            }
+        }
+        if diagnostics.iter().any(|d| d.severity() == Severity::Error) {
+            return diagnostics;
+        }
+
+        for (idx, section) in self.sections().iter().enumerate() {
+            let incoming = self.start_values(idx);
+            for (field, transition, incoming, min, max) in [
+                (
+                    Field::Vert,
+                    &section.vert,
+                    incoming.vert.0,
+                    limits.min_vert,
+                    limits.max_vert,
+                ),
+                (
+                    Field::Lat,
+                    &section.lat,
+                    incoming.lat.0,
+                    -limits.max_lat,
+                    limits.max_lat,
+                ),
+                (
+                    Field::Roll,
+                    &section.roll,
+                    incoming.roll_rate.0,
+                    -limits.max_roll_rate,
+                    limits.max_roll_rate,
+                ),
+            ] {
+                let resolved = transition.resolve(incoming);
+                let values = (0..=SAMPLES_PER_SECTION).map(|i| {
+                    let time = i as f32 / SAMPLES_PER_SECTION as f32 * section.length;
+                    incoming + resolved.sample(time, section.length)
+                });
+                let (low, high) = values.fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), v| {
+                    (lo.min(v), hi.max(v))
+                });
+                if !low.is_finite() || !high.is_finite() {
+                    diagnostics.push(Diagnostic::new(Some(idx), field, Issue::NotFinite));
+                } else if high > max {
+                    diagnostics.push(Diagnostic::new(
+                        Some(idx),
+                        field,
+                        Issue::AboveLimit {
+                            value: high,
+                            limit: max,
+                        },
+                    ));
+                } else if low < min {
+                    diagnostics.push(Diagnostic::new(
+                        Some(idx),
+                        field,
+                        Issue::BelowLimit {
+                            value: low,
+                            limit: min,
+                        },
+                    ));
+                }
+            }
+        }
+        diagnostics
+    }
+}
+
+fn timing_is_finite(transition: &Transition) -> bool {
+    transition.change.is_finite()
+        && transition.offset.is_finite()
+        && transition.duration.map_or(true, f32::is_finite)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        transitions::{FullTransition, TransitionFunction},
+        units::{DegPerSec, GForce},
+    };
+
+    // linear ramps of vert, lat and roll change, 1 s each
+    fn layout(changes: &[(f32, f32, f32)]) -> Transitions {
+        let mut transitions = Transitions::new(GForce(1.), GForce(0.), DegPerSec(0.));
+        *transitions.sections_mut() = changes
+            .iter()
+            .map(|&(vert, lat, roll)| FullTransition {
+                vert: Transition::new(TransitionFunction::Linear, vert),
+                lat: Transition::new(TransitionFunction::Linear, lat),
+                roll: Transition::new(TransitionFunction::Linear, roll),
+                length: 1.,
+                ..FullTransition::default()
+            })
+            .collect();
+        transitions
+    }
+
+    #[test]
+    fn a_layout_inside_the_limits_passes() {
+        let transitions = layout(&[(2., 1., 100.), (-3., -2., -200.)]);
+        assert_eq!(transitions.validate(&DesignLimits::default()), []);
+    }
+
+    #[test]
+    fn forces_are_checked_against_the_limits() {
+        let limits = DesignLimits::default();
+        // vert rises to 1 + 6 = 7g, lat to 2g and roll to 1200 deg/s
+        let rising = layout(&[(6., 2., 1200.)]);
+        assert_eq!(
+            rising.validate(&limits),
+            [
+                Diagnostic::new(
+                    Some(0),
+                    Field::Vert,
+                    Issue::AboveLimit {
+                        value: 7.,
+                        limit: limits.max_vert
+                    }
+                ),
+                Diagnostic::new(
+                    Some(0),
+                    Field::Lat,
+                    Issue::AboveLimit {
+                        value: 2.,
+                        limit: limits.max_lat
+                    }
+                ),
+                Diagnostic::new(
+                    Some(0),
+                    Field::Roll,
+                    Issue::AboveLimit {
+                        value: 1200.,
+                        limit: limits.max_roll_rate
+                    }
+                ),
+            ]
+        );
+        // and in the second section down to -2.5g, -2g and -1200 deg/s
+        let falling = layout(&[(0., 0., 0.), (-3.5, -2., -1200.)]);
+        let diagnostics = falling.validate(&limits);
+        assert_eq!(
+            diagnostics,
+            [
+                Diagnostic::new(
+                    Some(1),
+                    Field::Vert,
+                    Issue::BelowLimit {
+                        value: -2.5,
+                        limit: limits.min_vert
+                    }
+                ),
+                Diagnostic::new(
+                    Some(1),
+                    Field::Lat,
+                    Issue::BelowLimit {
+                        value: -2.,
+                        limit: -limits.max_lat
+                    }
+                ),
+                Diagnostic::new(
+                    Some(1),
+                    Field::Roll,
+                    Issue::BelowLimit {
+                        value: -1200.,
+                        limit: -limits.max_roll_rate
+                    }
+                ),
+            ]
+        );
+        assert!(diagnostics
+            .iter()
+            .all(|d| d.severity() == Severity::Warning));
+
+        // both pass with looser limits
+        let loose = DesignLimits {
+            max_vert: 7.5,
+            min_vert: -3.,
+            max_lat: 2.5,
+            max_roll_rate: 1500.,
+            ..limits
+        };
+        assert_eq!(rising.validate(&loose), []);
+        assert_eq!(falling.validate(&loose), []);
+    }
+
+    #[test]
+    fn errors_come_before_the_force_limits() {
+        let limits = DesignLimits::default();
+        let mut transitions = layout(&[(10., 0., 0.), (0., 0., 0.), (0., 0., 0.)]);
+        let sections = transitions.sections_mut();
+        sections[0].length = 0.05;
+        sections[1].length = 0.;
+        sections[1].speed = Some(-1.);
+        sections[2].lat.change = f32::NAN;
+        let mut start = transitions.start();
+        start.roll_rate = DegPerSec(f32::INFINITY);
+        transitions.set_start(start);
+
+        // the 11g in the first section is not reported until the errors are fixed
+        assert_eq!(
+            transitions.validate(&limits),
+            [
+                Diagnostic::new(None, Field::Roll, Issue::NotFinite),
+                Diagnostic::new(
+                    Some(0),
+                    Field::Length,
+                    Issue::ShortSection {
+                        length: 0.05,
+                        min: limits.min_section
+                    }
+                ),
+                Diagnostic::new(Some(1), Field::Length, Issue::InvalidLength(0.)),
+                Diagnostic::new(Some(1), Field::Speed, Issue::InvalidSpeed(-1.)),
+                Diagnostic::new(Some(2), Field::Lat, Issue::NotFinite),
+            ]
+        );
+    }
+
+    #[test]
+    fn messages_number_sections_from_one() {
+        let diagnostic = Diagnostic::new(Some(0), Field::Length, Issue::InvalidLength(-1.));
+        assert_eq!(diagnostic.severity(), Severity::Error);
+        assert_eq!(
+            diagnostic.to_string(),
+            "section 1 length: length -1s must be positive"
+        );
+        assert_eq!(
+            Diagnostic::new(None, Field::Speed, Issue::InvalidSpeed(0.)).to_string(),
+            "start speed: speed 0m/s must be positive"
+        );
+    }
+}
@@ -1,3 +1,2 @@
 // mod app;
 // pub use app::TemplateApp;
-use curve_core;
@@ -1,21 +1,32 @@
 // #![warn(clippy::all, rust_2018_idioms)]
 // #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
-use std::path::Path;
+use std::{ops::RangeInclusive, path::Path};
 
 use curve_core::{
-    fvd,
-    transitions::{TransitionFunction, Transitions},
+    expression::Expression,
+    fit::{self, FitReport, FitSettings},
+    fvd::Curve,
+    history::{Command, History},
+    keyframes::Keyframes,
+    params::Parameters,
+    project::{Project, SimulationSettings, StartState},
+    templates::Template,
+    transitions::{
+        ChangeMode, Easing, ElementTag, ForceLevels, ForceSample, FullTransition, SectionInfo,
+        Transition, TransitionFunction, Transitions,
+    },
+    units::{DegPerSec, GForce, LengthUnit, Meters, MetersPerSec, Seconds, SpeedUnit, UnitSystem},
+    validate::Severity,
 };
 use egui::{
-    plot::{Legend, Line, Plot, PlotPoints, VLine},
-    Color32, SidePanel, TopBottomPanel,
+    plot::{Legend, Line, Plot, PlotPoint, PlotPoints, Text, VLine},
+    Color32, Key, Modifiers,
 };
 use glam::{vec3, Mat4, Vec3};
 use three_d::{
-    Axes, BoundingBox, Camera, ClearState, ColorMaterial, CpuMaterial, CpuMesh, CpuTexture,
-    DirectionalLight, FrameOutput, Geometry, Gm, Mesh, OrbitControl, PhysicalMaterial, Srgba,
-    Texture2D, Window, WindowSettings,
+    Axes, Camera, ClearState, CpuMaterial, CpuMesh, CpuTexture, DirectionalLight, FrameOutput, Gm,
+    Mesh, OrbitControl, PhysicalMaterial, Srgba, Window, WindowSettings,
 };
 
 fn main() {
@@ -96,9 +107,17 @@ fn main() {
 
     // app data
 
-    let mut transitions = Transitions::new(1., 0., 0.);
-    let mut spline = fvd::create_spline(&transitions, Vec3::Y, 5.);
+    let mut project = Project::new(Transitions::new(GForce(1.), GForce(0.), DegPerSec(0.)));
+    let mut history = History::new();
+    let mut track = project.create_spline();
+    let mut solved_inputs = track_inputs(&project);
     let mut transition_idx = 0;
+    let mut project_path = "project.json".to_string();
+    let mut ride_path = "ride.csv".to_string();
+    let mut export_path = "export.nl2elem".to_string();
+    let mut export_curve = Curve::Heartline;
+    let mut new_parameter = String::new();
+    let mut fit_report: Option<FitReport> = None;
 
     window.render_loop(move |mut frame_input| {
         camera.set_viewport(frame_input.viewport);
@@ -108,6 +127,31 @@ fn main() {
             frame_input.viewport,
             frame_input.device_pixel_ratio,
             |gui_context| {
+                // a held pointer is a continuous drag, which undoes as a single step
+                if gui_context.input(|i| i.pointer.any_down()) {
+                    history.begin_group();
+                } else {
+                    history.end_group();
+                }
+                let redo_shortcut = Modifiers {
+                    shift: true,
+                    ..Modifiers::COMMAND
+                };
+                // a focused text field keeps the shortcuts for its own undo
+                if !gui_context.wants_keyboard_input() {
+                    if gui_context.input_mut(|i| {
+                        i.consume_key(redo_shortcut, Key::Z)
+                            || i.consume_key(Modifiers::COMMAND, Key::Y)
+                    }) {
+                        history.redo(&mut project);
+                    } else if gui_context
+                        .input_mut(|i| i.consume_key(Modifiers::COMMAND, Key::Z))
+                    {
+                        history.undo(&mut project);
+                    }
+                }
+                transition_idx = transition_idx.min(project.transitions.sections().len() - 1);
+
                 egui::TopBottomPanel::new(egui::panel::TopBottomSide::Bottom, "bottom_panel")
                     .default_height(400.0)
                     .resizable(true)
@@ -118,20 +162,601 @@ fn main() {
                             |ui| {
                                 ui.vertical(|ui| {
                                     ui.heading("Section Editor");
+                                    ui.horizontal(|ui| {
+                                        ui.text_edit_singleline(&mut project_path);
+                                        if ui.button("Save").clicked() {
+                                            if let Err(err) = project
+                                                .to_json()
+                                                .map_err(|e| e.to_string())
+                                                .and_then(|json| {
+                                                    std::fs::write(&project_path, json)
+                                                        .map_err(|e| e.to_string())
+                                                })
+                                            {
+                                                log::error!("failed to save project: {}", err);
+                                            }
+                                        }
+                                        if ui.button("Open").clicked() {
+                                            match std::fs::read_to_string(&project_path)
+                                                .map_err(|e| e.to_string())
+                                                .and_then(|json| {
+                                                    Project::from_json(&json)
+                                                        .map_err(|e| e.to_string())
+                                                }) {
+                                                Ok(loaded) => {
+                                                    project = loaded;
+                                                    history.clear();
+                                                    transition_idx = 0;
+                                                }
+                                                Err(err) => {
+                                                    log::error!("failed to open project: {}", err)
+                                                }
+                                            }
+                                        }
+                                        ui.text_edit_singleline(&mut ride_path);
+                                        if ui.button("Import Ride").clicked() {
+                                            // a csv of time, vertical, lateral, longitudinal g
+                                            match std::fs::read_to_string(&ride_path)
+                                                .map_err(|e| e.to_string())
+                                                .and_then(|csv| {
+                                                    fit::parse_csv(&csv)
+                                                        .and_then(|samples| {
+                                                            fit::fit(
+                                                                &samples,
+                                                                &FitSettings::default(),
+                                                            )
+                                                        })
+                                                        .map_err(|e| e.to_string())
+                                                }) {
+                                                Ok(fit) => {
+                                                    let _ = history.edit(&mut project, |t| {
+                                                        *t = fit.transitions;
+                                                        Ok::<_, ()>(())
+                                                    });
+                                                    fit_report = Some(fit.report);
+                                                    transition_idx = 0;
+                                                }
+                                                Err(err) => {
+                                                    log::error!("failed to import ride: {}", err)
+                                                }
+                                            }
+                                        }
+                                        ui.text_edit_singleline(&mut export_path);
+                                        egui::ComboBox::from_id_source("export_curve")
+                                            .selected_text(export_curve.name())
+                                            .show_ui(ui, |ui| {
+                                                for curve in Curve::ALL {
+                                                    ui.selectable_value(
+                                                        &mut export_curve,
+                                                        curve,
+                                                        curve.name(),
+                                                    );
+                                                }
+                                            });
+                                        if ui.button("Export").clicked() {
+                                            if let Err(err) = std::fs::write(
+                                                &export_path,
+                                                project.export_nolimits(&track, export_curve),
+                                            ) {
+                                                log::error!("failed to export track: {}", err);
+                                            }
+                                        }
+                                        if ui
+                                            .add_enabled(
+                                                history.can_undo(),
+                                                egui::Button::new("Undo"),
+                                            )
+                                            .clicked()
+                                        {
+                                            history.undo(&mut project);
+                                        }
+                                        if ui
+                                            .add_enabled(
+                                                history.can_redo(),
+                                                egui::Button::new("Redo"),
+                                            )
+                                            .clicked()
+                                        {
+                                            history.redo(&mut project);
+                                        }
+                                        transition_idx = transition_idx
+                                            .min(project.transitions.sections().len() - 1);
+                                    });
+                                    let mut dismiss = false;
+                                    if let Some(report) = &fit_report {
+                                        ui.horizontal(|ui| {
+                                            ui.label(format!(
+                                                "Fit: {} sections, normal rms {:.3}g (max {:.2}g), lateral rms {:.3}g (max {:.2}g)",
+                                                report.sections.len(),
+                                                report.vert_rms,
+                                                report.vert_max,
+                                                report.lat_rms,
+                                                report.lat_max
+                                            ));
+                                            dismiss = ui.button("Dismiss").clicked();
+                                        });
+                                    }
+                                    if dismiss {
+                                        fit_report = None;
+                                    }
+                                    ui.collapsing("Units", |ui| {
+                                        ui.horizontal(|ui| {
+                                            egui::ComboBox::from_id_source("unit_system")
+                                                .selected_text(project.units.name())
+                                                .show_ui(ui, |ui| {
+                                                    for preset in UnitSystem::PRESETS {
+                                                        ui.selectable_value(
+                                                            &mut project.units,
+                                                            preset,
+                                                            preset.name(),
+                                                        );
+                                                    }
+                                                });
+                                            ui.label("Length");
+                                            egui::ComboBox::from_id_source("length_unit")
+                                                .selected_text(project.units.length.suffix())
+                                                .show_ui(ui, |ui| {
+                                                    for unit in LengthUnit::ALL {
+                                                        ui.selectable_value(
+                                                            &mut project.units.length,
+                                                            unit,
+                                                            unit.suffix(),
+                                                        );
+                                                    }
+                                                });
+                                            ui.label("Speed");
+                                            egui::ComboBox::from_id_source("speed_unit")
+                                                .selected_text(project.units.speed.suffix())
+                                                .show_ui(ui, |ui| {
+                                                    for unit in SpeedUnit::ALL {
+                                                        ui.selectable_value(
+                                                            &mut project.units.speed,
+                                                            unit,
+                                                            unit.suffix(),
+                                                        );
+                                                    }
+                                                });
+                                        });
+                                        let units = project.units;
+                                        let mut start = project.start;
+                                        ui.horizontal(|ui| {
+                                            // converted only when edited, so an untouched value
+                                            // never picks up rounding from the round trip
+                                            ui.label("Start Speed");
+                                            let mut speed =
+                                                units.speed.from_mps(MetersPerSec(start.velocity));
+                                            if ui
+                                                .add(
+                                                    egui::DragValue::new(&mut speed)
+                                                        .clamp_range(0f32..=f32::INFINITY)
+                                                        .speed(0.1)
+                                                        .fixed_decimals(1)
+                                                        .suffix(units.speed.suffix()),
+                                                )
+                                                .changed()
+                                            {
+                                                start.velocity = units.speed.to_mps(speed).0;
+                                            }
+                                            ui.label("Start Height");
+                                            let mut height =
+                                                units.length.from_meters(Meters(start.position.y));
+                                            if ui
+                                                .add(
+                                                    egui::DragValue::new(&mut height)
+                                                        .speed(0.1)
+                                                        .fixed_decimals(1)
+                                                        .suffix(units.length.suffix()),
+                                                )
+                                                .changed()
+                                            {
+                                                start.position.y = units.length.to_meters(height).0;
+                                            }
+                                        });
+                                        let before = project.start;
+                                        if start != before {
+                                            history.execute(
+                                                &mut project,
+                                                Command::SetStart {
+                                                    before,
+                                                    after: start,
+                                                },
+                                            );
+                                        }
+                                        let (low, high) = track.heartline.points().iter().fold(
+                                            (f32::INFINITY, f32::NEG_INFINITY),
+                                            |(low, high), (pos, _)| (low.min(pos.y), high.max(pos.y)),
+                                        );
+                                        if low <= high {
+                                            ui.label(format!(
+                                                "Track: {} long, {} from lowest to highest point",
+                                                units.format_length(track.heartline.length()),
+                                                units.format_length(Meters(high - low))
+                                            ));
+                                        }
+                                    });
+                                    ui.collapsing("Parameters", |ui| {
+                                        for (name, value) in project.parameters.clone() {
+                                            ui.horizontal(|ui| {
+                                                ui.label(format!(
+                                                    "{} ({} sections)",
+                                                    name,
+                                                    project.transitions.parameter_users(&name)
+                                                ));
+                                                let mut edited = value;
+                                                rounded_drag_value(
+                                                    ui,
+                                                    &mut edited,
+                                                    f32::NEG_INFINITY..=f32::INFINITY,
+                                                    0.1,
+                                                    "",
+                                                );
+                                                let after = if ui.button("Remove").clicked() {
+                                                    None
+                                                } else {
+                                                    Some(edited)
+                                                };
+                                                if after != Some(value) {
+                                                    history.execute(
+                                                        &mut project,
+                                                        Command::SetParameter {
+                                                            name,
+                                                            before: Some(value),
+                                                            after,
+                                                        },
+                                                    );
+                                                }
+                                            });
+                                        }
+                                        ui.horizontal(|ui| {
+                                            ui.text_edit_singleline(&mut new_parameter);
+                                            if ui
+                                                .add_enabled(
+                                                    !new_parameter.is_empty()
+                                                        && !project
+                                                            .parameters
+                                                            .contains_key(&new_parameter),
+                                                    egui::Button::new("Add Parameter"),
+                                                )
+                                                .clicked()
+                                            {
+                                                history.execute(
+                                                    &mut project,
+                                                    Command::SetParameter {
+                                                        name: std::mem::take(&mut new_parameter),
+                                                        before: None,
+                                                        after: Some(0.),
+                                                    },
+                                                );
+                                            }
+                                        });
+                                    });
+                                    let diagnostics = project.validate();
+                                    let errors = diagnostics
+                                        .iter()
+                                        .filter(|d| d.severity() == Severity::Error)
+                                        .count();
+                                    // the force table is unusable while there are errors
+                                    let discontinuities = if errors == 0 {
+                                        project.transitions.discontinuities(&project.joins)
+                                    } else {
+                                        Vec::new()
+                                    };
+                                    ui.collapsing(
+                                        format!(
+                                            "Diagnostics ({} errors, {} warnings)",
+                                            errors,
+                                            diagnostics.len() - errors + discontinuities.len()
+                                        ),
+                                        |ui| {
+                                            for diagnostic in &diagnostics {
+                                                let color = match diagnostic.severity() {
+                                                    Severity::Error => Color32::RED,
+                                                    Severity::Warning => Color32::YELLOW,
+                                                };
+                                                let label = ui.add(
+                                                    egui::Label::new(
+                                                        egui::RichText::new(diagnostic.to_string())
+                                                            .color(color),
+                                                    )
+                                                    .sense(egui::Sense::click()),
+                                                );
+                                                if label.clicked() {
+                                                    transition_idx = diagnostic
+                                                        .section
+                                                        .unwrap_or(transition_idx);
+                                                }
+                                            }
+                                            for discontinuity in &discontinuities {
+                                                let label = ui.add(
+                                                    egui::Label::new(
+                                                        egui::RichText::new(
+                                                            discontinuity.to_string(),
+                                                        )
+                                                        .color(Color32::YELLOW),
+                                                    )
+                                                    .sense(egui::Sense::click()),
+                                                );
+                                                if label.clicked() {
+                                                    transition_idx = discontinuity.join;
+                                                }
+                                            }
+                                            ui.horizontal(|ui| {
+                                                ui.checkbox(
+                                                    &mut project.joins.curvature,
+                                                    "Check curvature",
+                                                );
+                                                ui.label("Blend");
+                                                ui.add(
+                                                    egui::DragValue::new(
+                                                        &mut project.joins.blend_length,
+                                                    )
+                                                    .speed(0.01)
+                                                    .clamp_range(0.02..=2.0)
+                                                    .suffix("s"),
+                                                );
+                                                if ui
+                                                    .add_enabled(
+                                                        !discontinuities.is_empty(),
+                                                        egui::Button::new("Auto-Smooth"),
+                                                    )
+                                                    .clicked()
+                                                {
+                                                    let settings = project.joins;
+                                                    let _ = history.edit(&mut project, |t| {
+                                                        t.smooth_joins(&settings);
+                                                        Ok::<_, ()>(())
+                                                    });
+                                                }
+                                            });
+                                            ui.separator();
+                                            let limits = &mut project.limits;
+                                            ui.horizontal(|ui| {
+                                                ui.label("Normal");
+                                                ui.add(
+                                                    egui::DragValue::new(&mut limits.min_vert)
+                                                        .speed(0.1)
+                                                        .suffix("g"),
+                                                );
+                                                ui.label("to");
+                                                ui.add(
+                                                    egui::DragValue::new(&mut limits.max_vert)
+                                                        .speed(0.1)
+                                                        .suffix("g"),
+                                                );
+                                                ui.label("Lateral");
+                                                ui.add(
+                                                    egui::DragValue::new(&mut limits.max_lat)
+                                                        .speed(0.1)
+                                                        .clamp_range(0.0..=f32::MAX)
+                                                        .suffix("g"),
+                                                );
+                                            });
+                                            ui.horizontal(|ui| {
+                                                ui.label("Roll rate");
+                                                ui.add(
+                                                    egui::DragValue::new(&mut limits.max_roll_rate)
+                                                        .speed(10.)
+                                                        .clamp_range(0.0..=f32::MAX)
+                                                        .suffix("deg/s"),
+                                                );
+                                                ui.label("Min section");
+                                                ui.add(
+                                                    egui::DragValue::new(&mut limits.min_section)
+                                                        .speed(0.01)
+                                                        .clamp_range(0.0..=f32::MAX)
+                                                        .suffix("s"),
+                                                );
+                                            });
+                                        },
+                                    );
+                                    ui.horizontal(|ui| {
+                                        let last = project.transitions.sections().len() - 1;
+                                        ui.label("Section");
+                                        ui.add(
+                                            egui::DragValue::new(&mut transition_idx)
+                                                .clamp_range(0..=last)
+                                                .custom_formatter(|n, _| format!("{}", n + 1.))
+                                                .custom_parser(|s| {
+                                                    s.parse::<f64>().ok().map(|n| n - 1.)
+                                                })
+                                                .suffix(format!(" / {}", last + 1)),
+                                        );
+                                        let add = ui.button("Add").clicked();
+                                        let duplicate = ui.button("Duplicate").clicked();
+                                        let delete = ui.button("Delete").clicked();
+                                        let split = ui.button("Split").clicked();
+                                        let merge = ui.button("Merge").clicked();
+                                        let move_left = ui.button("Move Left").clicked();
+                                        let move_right = ui.button("Move Right").clicked();
+                                        let mirror = ui.button("Mirror").clicked();
+                                        let invert = ui.button("Invert").clicked();
+                                        let mut template = None;
+                                        ui.menu_button("Insert Element", |ui| {
+                                            for example in Template::EXAMPLES {
+                                                if ui.button(example.name()).clicked() {
+                                                    template = Some(example);
+                                                    ui.close_menu();
+                                                }
+                                            }
+                                        });
+                                        let result = if add {
+                                            history
+                                                .edit(&mut project, |t| {
+                                                    t.insert(transition_idx + 1, Default::default())
+                                                })
+                                                .map(|_| transition_idx += 1)
+                                        } else if duplicate {
+                                            history
+                                                .edit(&mut project, |t| t.duplicate(transition_idx))
+                                                .map(|_| transition_idx += 1)
+                                        } else if delete {
+                                            history
+                                                .edit(&mut project, |t| t.remove(transition_idx))
+                                                .map(|_| ())
+                                        } else if split {
+                                            let half = project.transitions.sections()
+                                                [transition_idx]
+                                                .length
+                                                / 2.;
+                                            history.edit(&mut project, |t| {
+                                                t.split(transition_idx, Seconds(half))
+                                            })
+                                        } else if merge {
+                                            history.edit(&mut project, |t| t.merge(transition_idx))
+                                        } else if move_left {
+                                            history
+                                                .edit(&mut project, |t| {
+                                                    t.move_section(
+                                                        transition_idx,
+                                                        transition_idx.saturating_sub(1),
+                                                    )
+                                                })
+                                                .map(|_| {
+                                                    transition_idx =
+                                                        transition_idx.saturating_sub(1)
+                                                })
+                                        } else if move_right {
+                                            history
+                                                .edit(&mut project, |t| {
+                                                    t.move_section(
+                                                        transition_idx,
+                                                        transition_idx + 1,
+                                                    )
+                                                })
+                                                .map(|_| transition_idx += 1)
+                                        } else if mirror {
+                                            history.edit(&mut project, |t| {
+                                                t.mirror(transition_idx..transition_idx + 1)
+                                            })
+                                        } else if invert {
+                                            history.edit(&mut project, |t| {
+                                                t.invert(transition_idx..transition_idx + 1)
+                                            })
+                                        } else if let Some(template) = template {
+                                            history
+                                                .edit(&mut project, |t| {
+                                                    t.insert_template(transition_idx + 1, &template)
+                                                })
+                                                .map(|_| transition_idx += 1)
+                                        } else {
+                                            Ok(())
+                                        };
+                                        if let Err(err) = result {
+                                            log::error!("failed to edit sections: {}", err);
+                                        }
+                                        transition_idx = transition_idx
+                                            .min(project.transitions.sections().len() - 1);
+                                    });
+                                    if let Some(group) =
+                                        project.transitions.group_of(transition_idx).cloned()
+                                    {
+                                        ui.horizontal(|ui| {
+                                            ui.label(format!("Group: {}", group.name));
+                                            let mut collapsed = group.collapsed;
+                                            let collapse =
+                                                ui.checkbox(&mut collapsed, "Collapsed").changed();
+                                            let mut scale = group.scale;
+                                            ui.label("Scale");
+                                            let rescale = ui
+                                                .add(
+                                                    egui::DragValue::new(&mut scale)
+                                                        .clamp_range(0.1f32..=10f32)
+                                                        .speed(0.01)
+                                                        .fixed_decimals(2),
+                                                )
+                                                .changed();
+                                            let move_left = ui.button("Move Left").clicked();
+                                            let move_right = ui.button("Move Right").clicked();
+                                            let mirror = ui.button("Mirror").clicked();
+                                            let invert = ui.button("Invert").clicked();
+                                            let duplicate = ui.button("Duplicate Group").clicked();
+                                            let regenerate = ui
+                                                .add_enabled(
+                                                    group.template.is_some(),
+                                                    egui::Button::new("Regenerate"),
+                                                )
+                                                .clicked();
+                                            let ungroup = ui.button("Ungroup").clicked();
+                                            let id = group.id;
+                                            let result = if collapse {
+                                                history.edit(&mut project, |t| {
+                                                    t.set_collapsed(id, collapsed)
+                                                })
+                                            } else if rescale {
+                                                history.edit(&mut project, |t| {
+                                                    t.scale_group(id, scale / group.scale)
+                                                })
+                                            } else if move_left || move_right {
+                                                let range = project
+                                                    .transitions
+                                                    .group_range(id)
+                                                    .unwrap_or(transition_idx..transition_idx + 1);
+                                                // a neighbouring group is stepped over whole
+                                                let step = |idx: Option<usize>| {
+                                                    idx.and_then(|idx| {
+                                                        project.transitions.group_of(idx)
+                                                    })
+                                                    .and_then(|neighbour| {
+                                                        project.transitions.group_range(neighbour.id)
+                                                    })
+                                                    .map_or(1, |neighbour| neighbour.len())
+                                                };
+                                                let to = if move_left {
+                                                    range
+                                                        .start
+                                                        .saturating_sub(step(range.start.checked_sub(1)))
+                                                } else {
+                                                    range.start + step(Some(range.end))
+                                                };
+                                                history
+                                                    .edit(&mut project, |t| t.move_group(id, to))
+                                                    .map(|_| {
+                                                        transition_idx =
+                                                            transition_idx + to - range.start
+                                                    })
+                                            } else if mirror {
+                                                history.edit(&mut project, |t| t.mirror_group(id))
+                                            } else if invert {
+                                                history.edit(&mut project, |t| t.invert_group(id))
+                                            } else if duplicate {
+                                                history
+                                                    .edit(&mut project, |t| t.duplicate_group(id))
+                                                    .map(|_| ())
+                                            } else if regenerate {
+                                                history
+                                                    .edit(&mut project, |t| t.regenerate_group(id))
+                                            } else if ungroup {
+                                                history.edit(&mut project, |t| t.ungroup(id))
+                                            } else {
+                                                Ok(())
+                                            };
+                                            if let Err(err) = result {
+                                                log::error!("failed to edit group: {}", err);
+                                            }
+                                            transition_idx = transition_idx
+                                                .min(project.transitions.sections().len() - 1);
+                                        });
+                                    }
+                                    let section_before =
+                                        project.transitions.sections()[transition_idx].clone();
                                     {
                                         let mut transition =
-                                            transitions.transitions[transition_idx];
+                                            project.transitions.sections()[transition_idx].clone();
                                         ui.horizontal(|ui| {
                                             ui.label("Length");
-                                            ui.add(
-                                                egui::DragValue::new(&mut transition.length)
-                                                    .clamp_range(0.1f32..=f32::INFINITY)
-                                                    .suffix("s")
-                                                    .speed(0.01)
-                                                    .fixed_decimals(1), // .update_while_editing(true) TODO patch this into egui 0.22
+                                            rounded_drag_value(
+                                                ui,
+                                                &mut transition.length,
+                                                0.1..=f32::INFINITY,
+                                                0.01,
+                                                "s",
+                                            );
+                                            binding_ui(
+                                                ui,
+                                                "binding_length",
+                                                &mut transition.bindings.length,
+                                                &project.parameters,
                                             );
-                                            transition.length =
-                                                (transition.length * 10.).round() / 10.0;
                                         });
                                         ui.horizontal(|ui| {
                                             let mut fixed_speed = transition.speed.is_some();
@@ -143,227 +768,270 @@ fn main() {
                                                 transition.speed = None;
                                             }
                                             if let Some(fixed_speed) = &mut transition.speed {
-                                                ui.add(
-                                                    egui::Slider::new(fixed_speed, 1f32..=500f32)
-                                                        .logarithmic(true)
-                                                        .fixed_decimals(0)
-                                                        .suffix("m/s"),
-                                                );
+                                                let unit = project.units.speed;
+                                                let mut shown =
+                                                    unit.from_mps(MetersPerSec(*fixed_speed));
+                                                let range = unit.from_mps(MetersPerSec(1.))
+                                                    ..=unit.from_mps(MetersPerSec(500.));
+                                                if ui
+                                                    .add(
+                                                        egui::Slider::new(&mut shown, range)
+                                                            .logarithmic(true)
+                                                            .fixed_decimals(0)
+                                                            .suffix(unit.suffix()),
+                                                    )
+                                                    .changed()
+                                                {
+                                                    *fixed_speed = unit.to_mps(shown).0;
+                                                }
                                             }
                                         });
+                                        section_info_ui(ui, &mut transition.info);
 
-                                        transitions.transitions[transition_idx] = transition;
+                                        if project.transitions.sections()[transition_idx]
+                                            != transition
+                                        {
+                                            project.transitions.sections_mut()[transition_idx] =
+                                                transition;
+                                        }
                                     }
+                                    let section_length =
+                                        project.transitions.sections()[transition_idx].length;
+                                    let incoming = project.transitions.start_values(transition_idx);
                                     {
-                                        let mut vert_transition =
-                                            transitions.transitions[transition_idx].vert;
+                                        let mut vert_transition = project.transitions.sections()
+                                            [transition_idx]
+                                            .vert
+                                            .clone();
+                                        let mut vert_binding = project.transitions.sections()
+                                            [transition_idx]
+                                            .bindings
+                                            .vert
+                                            .clone();
                                         ui.vertical(|ui| {
                                             ui.heading("Normal");
                                             ui.horizontal(|ui| {
                                                 ui.label("Type");
-                                                egui::ComboBox::from_id_source(
+                                                transition_function_ui(
+                                                    ui,
                                                     "type_combobox_normal",
+                                                    &mut vert_transition.function,
+                                                );
+                                                if rounded_drag_value(
+                                                    ui,
+                                                    &mut vert_transition.change,
+                                                    -10.0..=10.0,
+                                                    0.1,
+                                                    "g",
                                                 )
-                                                .selected_text(match vert_transition.function {
-                                                    TransitionFunction::Linear => "Linear",
-                                                    TransitionFunction::Quadratic => "Quadratic",
-                                                    TransitionFunction::Cubic => "Cubic",
-                                                    TransitionFunction::Plateau => "Plateau",
-                                                })
-                                                .show_ui(ui, |ui| {
-                                                    ui.selectable_value(
-                                                        &mut vert_transition.function,
-                                                        TransitionFunction::Linear,
-                                                        "Linear",
-                                                    );
-                                                    ui.selectable_value(
-                                                        &mut vert_transition.function,
-                                                        TransitionFunction::Quadratic,
-                                                        "Quadratic",
-                                                    );
-                                                    ui.selectable_value(
-                                                        &mut vert_transition.function,
-                                                        TransitionFunction::Cubic,
-                                                        "Cubic",
-                                                    );
-                                                    ui.selectable_value(
-                                                        &mut vert_transition.function,
-                                                        TransitionFunction::Plateau,
-                                                        "Plateau",
-                                                    );
-                                                });
-                                                if ui
-                                                    .add(
-                                                        egui::DragValue::new(
-                                                            &mut vert_transition.change,
-                                                        )
-                                                        .clamp_range(-10f32..=10f32)
-                                                        .suffix("g")
-                                                        .speed(0.1)
-                                                        .fixed_decimals(1), // .update_while_editing(true),
-                                                    )
-                                                    .secondary_clicked()
+                                                .secondary_clicked()
                                                 {
                                                     vert_transition.change = 0.;
-                                                };
-                                                vert_transition.change =
-                                                    (vert_transition.change * 10.).round() / 10.0;
+                                                }
+                                                change_mode_ui(
+                                                    ui,
+                                                    &mut vert_transition,
+                                                    incoming.vert.0,
+                                                );
+                                                binding_ui(
+                                                    ui,
+                                                    "binding_normal",
+                                                    &mut vert_binding,
+                                                    &project.parameters,
+                                                );
                                             });
+                                            transition_timing_ui(
+                                                ui,
+                                                &mut vert_transition,
+                                                section_length,
+                                            );
                                         });
-                                        transitions.transitions[transition_idx].vert =
-                                            vert_transition;
+                                        let section =
+                                            &project.transitions.sections()[transition_idx];
+                                        if section.vert != vert_transition
+                                            || section.bindings.vert != vert_binding
+                                        {
+                                            let section = &mut project.transitions.sections_mut()
+                                                [transition_idx];
+                                            section.vert = vert_transition;
+                                            section.bindings.vert = vert_binding;
+                                        }
                                     }
                                     {
-                                        let mut lat_transition =
-                                            transitions.transitions[transition_idx].lat;
+                                        let mut lat_transition = project.transitions.sections()
+                                            [transition_idx]
+                                            .lat
+                                            .clone();
+                                        let mut lat_binding = project.transitions.sections()
+                                            [transition_idx]
+                                            .bindings
+                                            .lat
+                                            .clone();
                                         ui.vertical(|ui| {
                                             ui.heading("Lateral");
                                             ui.horizontal(|ui| {
                                                 ui.label("Type");
-                                                egui::ComboBox::from_id_source("type_combobox_lat")
-                                                    .selected_text(match lat_transition.function {
-                                                        TransitionFunction::Linear => "Linear",
-                                                        TransitionFunction::Quadratic => {
-                                                            "Quadratic"
-                                                        }
-                                                        TransitionFunction::Cubic => "Cubic",
-                                                        TransitionFunction::Plateau => "Plateau",
-                                                    })
-                                                    .show_ui(ui, |ui| {
-                                                        ui.selectable_value(
-                                                            &mut lat_transition.function,
-                                                            TransitionFunction::Linear,
-                                                            "Linear",
-                                                        );
-                                                        ui.selectable_value(
-                                                            &mut lat_transition.function,
-                                                            TransitionFunction::Quadratic,
-                                                            "Quadratic",
-                                                        );
-                                                        ui.selectable_value(
-                                                            &mut lat_transition.function,
-                                                            TransitionFunction::Cubic,
-                                                            "Cubic",
-                                                        );
-                                                        ui.selectable_value(
-                                                            &mut lat_transition.function,
-                                                            TransitionFunction::Plateau,
-                                                            "Plateau",
-                                                        );
-                                                    });
-                                                if ui
-                                                    .add(
-                                                        egui::DragValue::new(
-                                                            &mut lat_transition.change,
-                                                        )
-                                                        .clamp_range(-10f32..=10f32)
-                                                        .suffix("g")
-                                                        .speed(0.1)
-                                                        .fixed_decimals(1), // .update_while_editing(true),
-                                                    )
-                                                    .secondary_clicked()
+                                                transition_function_ui(
+                                                    ui,
+                                                    "type_combobox_lat",
+                                                    &mut lat_transition.function,
+                                                );
+                                                if rounded_drag_value(
+                                                    ui,
+                                                    &mut lat_transition.change,
+                                                    -10.0..=10.0,
+                                                    0.1,
+                                                    "g",
+                                                )
+                                                .secondary_clicked()
                                                 {
                                                     lat_transition.change = 0.;
-                                                };
-                                                lat_transition.change =
-                                                    (lat_transition.change * 10.).round() / 10.0;
+                                                }
+                                                change_mode_ui(ui, &mut lat_transition, incoming.lat.0);
+                                                binding_ui(
+                                                    ui,
+                                                    "binding_lateral",
+                                                    &mut lat_binding,
+                                                    &project.parameters,
+                                                );
                                             });
+                                            transition_timing_ui(
+                                                ui,
+                                                &mut lat_transition,
+                                                section_length,
+                                            );
                                         });
-                                        transitions.transitions[transition_idx].lat =
-                                            lat_transition;
+                                        let section =
+                                            &project.transitions.sections()[transition_idx];
+                                        if section.lat != lat_transition
+                                            || section.bindings.lat != lat_binding
+                                        {
+                                            let section = &mut project.transitions.sections_mut()
+                                                [transition_idx];
+                                            section.lat = lat_transition;
+                                            section.bindings.lat = lat_binding;
+                                        }
                                     }
                                     {
-                                        let mut roll_transition =
-                                            transitions.transitions[transition_idx].roll;
+                                        let mut roll_transition = project.transitions.sections()
+                                            [transition_idx]
+                                            .roll
+                                            .clone();
+                                        let mut roll_binding = project.transitions.sections()
+                                            [transition_idx]
+                                            .bindings
+                                            .roll
+                                            .clone();
                                         ui.vertical(|ui| {
                                             ui.heading("Roll");
                                             ui.horizontal(|ui| {
                                                 ui.label("Type");
-                                                egui::ComboBox::from_id_source(
+                                                transition_function_ui(
+                                                    ui,
                                                     "type_combobox_roll",
+                                                    &mut roll_transition.function,
+                                                );
+                                                if rounded_drag_value(
+                                                    ui,
+                                                    &mut roll_transition.change,
+                                                    -1000.0..=1000.0,
+                                                    1.,
+                                                    "°/s",
                                                 )
-                                                .selected_text(match roll_transition.function {
-                                                    TransitionFunction::Linear => "Linear",
-                                                    TransitionFunction::Quadratic => "Quadratic",
-                                                    TransitionFunction::Cubic => "Cubic",
-                                                    TransitionFunction::Plateau => "Plateau",
-                                                })
-                                                .show_ui(ui, |ui| {
-                                                    ui.selectable_value(
-                                                        &mut roll_transition.function,
-                                                        TransitionFunction::Linear,
-                                                        "Linear",
-                                                    );
-                                                    ui.selectable_value(
-                                                        &mut roll_transition.function,
-                                                        TransitionFunction::Quadratic,
-                                                        "Quadratic",
-                                                    );
-                                                    ui.selectable_value(
-                                                        &mut roll_transition.function,
-                                                        TransitionFunction::Cubic,
-                                                        "Cubic",
-                                                    );
-                                                    ui.selectable_value(
-                                                        &mut roll_transition.function,
-                                                        TransitionFunction::Plateau,
-                                                        "Plateau",
-                                                    );
-                                                });
-                                                if ui
-                                                    .add(
-                                                        egui::DragValue::new(
-                                                            &mut roll_transition.change,
-                                                        )
-                                                        .clamp_range(-1000f32..=1000f32)
-                                                        .suffix("°/s")
-                                                        .speed(1.)
-                                                        .fixed_decimals(1), // .update_while_editing(true),
-                                                    )
-                                                    .secondary_clicked()
+                                                .secondary_clicked()
                                                 {
-                                                    roll_transition.change = 0.
-                                                };
-                                                roll_transition.change =
-                                                    (roll_transition.change * 10.).round() / 10.0;
+                                                    roll_transition.change = 0.;
+                                                }
+                                                change_mode_ui(
+                                                    ui,
+                                                    &mut roll_transition,
+                                                    incoming.roll_rate.0,
+                                                );
+                                                binding_ui(
+                                                    ui,
+                                                    "binding_roll",
+                                                    &mut roll_binding,
+                                                    &project.parameters,
+                                                );
                                             });
+                                            transition_timing_ui(
+                                                ui,
+                                                &mut roll_transition,
+                                                section_length,
+                                            );
                                         });
-                                        transitions.transitions[transition_idx].roll =
-                                            roll_transition;
+                                        let section =
+                                            &project.transitions.sections()[transition_idx];
+                                        if section.roll != roll_transition
+                                            || section.bindings.roll != roll_binding
+                                        {
+                                            let section = &mut project.transitions.sections_mut()
+                                                [transition_idx];
+                                            section.roll = roll_transition;
+                                            section.bindings.roll = roll_binding;
+                                        }
                                     }
+                                    // editing a bound value edits the parameter, and so every
+                                    // section using it
+                                    history.edit_section(
+                                        &mut project,
+                                        transition_idx,
+                                        section_before,
+                                    );
                                 });
                                 let plot = Plot::new("Transitions").allow_scroll(false);
-                                let vert = (0..=(transitions.length() * 50.) as usize).map(|v| {
-                                    [
-                                        v as f64 / 50.0,
-                                        (transitions
-                                            .interpolate(v as f32 / 50.)
-                                            .map(|v| v.0)
-                                            .unwrap_or(f32::INFINITY)
-                                            as f64),
-                                    ]
-                                });
-                                let lat = (0..=(transitions.length() * 50.) as usize).map(|v| {
-                                    [
-                                        v as f64 / 50.0,
-                                        transitions
-                                            .interpolate(v as f32 / 50.)
-                                            .map(|v| v.1)
-                                            .unwrap_or(f32::INFINITY)
-                                            as f64,
-                                    ]
-                                });
-                                let roll = (0..=(transitions.length() * 50.) as usize).map(|v| {
-                                    [
-                                        v as f64 / 50.0,
-                                        (transitions
-                                            .interpolate(v as f32 / 50.)
-                                            .map(|v| v.2)
-                                            .unwrap_or(f32::INFINITY)
-                                            as f64)
-                                            .to_radians(),
-                                    ]
-                                });
+                                let mut section_start = 0.;
+                                let section_marks = project
+                                    .transitions
+                                    .sections()
+                                    .iter()
+                                    .enumerate()
+                                    .filter_map(|(idx, section)| {
+                                        let start = section_start;
+                                        section_start += section.length as f64;
+                                        let colour = section
+                                            .info
+                                            .colour
+                                            .map_or(Color32::GRAY, |[r, g, b]| {
+                                                Color32::from_rgb(r, g, b)
+                                            });
+                                        // collapsed groups get a single mark at their start
+                                        match project.transitions.group_of(idx) {
+                                            Some(group) if group.collapsed => {
+                                                let first = idx == 0
+                                                    || project.transitions.sections()[idx - 1]
+                                                        .group
+                                                        != Some(group.id);
+                                                first.then(|| (start, group.name.clone(), colour))
+                                            }
+                                            _ => Some((start, section.label(idx), colour)),
+                                        }
+                                    })
+                                    .collect::<Vec<_>>();
+                                let samples = (0..=(project.transitions.length().0 * 50.)
+                                    as usize)
+                                    .filter_map(|v| {
+                                        let time = v as f32 / 50.;
+                                        project
+                                            .transitions
+                                            .interpolate(Seconds(time))
+                                            .map(|sample| (time as f64, sample))
+                                    })
+                                    .collect::<Vec<_>>();
+                                let curve = |value: fn(&ForceSample) -> f64, selected: bool| {
+                                    samples
+                                        .iter()
+                                        .filter(|(_, sample)| {
+                                            !selected || sample.section == transition_idx
+                                        })
+                                        .map(|(time, sample)| [*time, value(sample)])
+                                        .collect::<PlotPoints>()
+                                };
+                                let vert: fn(&ForceSample) -> f64 =
+                                    |sample| sample.vert.0 as f64;
+                                let lat: fn(&ForceSample) -> f64 = |sample| sample.lat.0 as f64;
+                                let roll: fn(&ForceSample) -> f64 =
+                                    |sample| sample.roll_rate.to_radians() as f64;
 
                                 plot
                                     // .custom_y_axes(vec![
@@ -379,27 +1047,44 @@ fn main() {
                                         format!("{}g", (v * 100_000_000.0).round() / 100_000_000.0)
                                     })
                                     .show(ui, |plot_ui| {
-                                        plot_ui.line(
-                                            Line::new(PlotPoints::from_iter(vert))
-                                                .name("vertical")
-                                                .color(Color32::BLUE),
-                                        );
-                                        plot_ui.line(
-                                            Line::new(PlotPoints::from_iter(lat))
-                                                .name("lateral")
-                                                .color(Color32::GREEN),
-                                        );
-                                        plot_ui.line(
-                                            Line::new(PlotPoints::from_iter(roll))
-                                                .name("roll")
-                                                .color(Color32::RED),
-                                        );
+                                        for (value, name, colour) in [
+                                            (vert, "vertical", Color32::BLUE),
+                                            (lat, "lateral", Color32::GREEN),
+                                            (roll, "roll", Color32::RED),
+                                        ] {
+                                            plot_ui.line(
+                                                Line::new(curve(value, false))
+                                                    .name(name)
+                                                    .color(colour),
+                                            );
+                                            // the selected section drawn over the top
+                                            plot_ui.line(
+                                                Line::new(curve(value, true))
+                                                    .color(colour)
+                                                    .width(3.),
+                                            );
+                                        }
+                                        let top = plot_ui.plot_bounds().max()[1];
+                                        for (start, label, colour) in section_marks {
+                                            plot_ui.vline(VLine::new(start).color(colour));
+                                            plot_ui.text(
+                                                Text::new(PlotPoint::new(start, top), label)
+                                                    .anchor(egui::Align2::LEFT_TOP)
+                                                    .color(colour),
+                                            );
+                                        }
                                     });
                             },
                         );
                     });
             },
         );
+        // solved once the frame's edits are done, the readouts and export share it
+        let inputs = track_inputs(&project);
+        if inputs != solved_inputs {
+            track = project.create_spline();
+            solved_inputs = inputs;
+        }
         control.handle_events(&mut camera, &mut frame_input.events);
 
         let screen = frame_input.screen();
@@ -417,6 +1102,307 @@ fn main() {
     });
 }
 
+fn transition_function_ui(ui: &mut egui::Ui, id: &str, function: &mut TransitionFunction) {
+    egui::ComboBox::from_id_source(id)
+        .selected_text(function.name())
+        .show_ui(ui, |ui| {
+            for variant in TransitionFunction::ALL {
+                if ui
+                    .selectable_label(function.name() == variant.name(), variant.name())
+                    .clicked()
+                    && function.name() != variant.name()
+                {
+                    *function = variant;
+                }
+            }
+            if ui
+                .selectable_label(
+                    matches!(function, TransitionFunction::Expression(_)),
+                    "Expression",
+                )
+                .clicked()
+                && !matches!(function, TransitionFunction::Expression(_))
+            {
+                *function =
+                    TransitionFunction::Expression(Expression::parse("t").expect("valid formula"));
+            }
+            if ui
+                .selectable_label(
+                    matches!(function, TransitionFunction::Keyframes(_)),
+                    "Keyframes",
+                )
+                .clicked()
+                && !matches!(function, TransitionFunction::Keyframes(_))
+            {
+                *function = TransitionFunction::Keyframes(Keyframes::default());
+            }
+        });
+    if let TransitionFunction::Keyframes(keyframes) = function {
+        keyframes_ui(ui, keyframes);
+    }
+    if let TransitionFunction::Expression(expression) = function {
+        // text being typed lives in egui memory while focused, so invalid drafts survive
+        let text_id = ui.make_persistent_id((id, "formula"));
+        let mut text = ui
+            .data_mut(|d| d.get_temp::<String>(text_id))
+            .unwrap_or_else(|| expression.source().to_string());
+        let parsed = Expression::parse(&text);
+        let colour = if parsed.is_ok() {
+            ui.visuals().text_color()
+        } else {
+            Color32::RED
+        };
+        let mut response = ui.add(egui::TextEdit::singleline(&mut text).text_color(colour));
+        if let Err(err) = parsed {
+            response = response.on_hover_text(err.to_string());
+        }
+        if response.changed() {
+            if let Ok(parsed) = Expression::parse(&text) {
+                *expression = parsed;
+            }
+        }
+        if response.has_focus() {
+            ui.data_mut(|d| d.insert_temp(text_id, text));
+        } else {
+            ui.data_mut(|d| d.remove::<String>(text_id));
+        }
+    }
+    if let TransitionFunction::Bezier { x1, y1, x2, y2 } = function {
+        // x has to stay in [0, 1] for the curve to be a function of t, y may overshoot
+        for (value, range) in [
+            (x1, 0f32..=1f32),
+            (y1, -1f32..=2f32),
+            (x2, 0f32..=1f32),
+            (y2, -1f32..=2f32),
+        ] {
+            ui.add(
+                egui::DragValue::new(value)
+                    .clamp_range(range)
+                    .speed(0.01)
+                    .fixed_decimals(2),
+            );
+        }
+    }
+    if let TransitionFunction::Plateau {
+        ramp_in,
+        ramp_out,
+        ease_in,
+        ease_out,
+    } = function
+    {
+        for (label, ramp, easing) in [("in", ramp_in, ease_in), ("out", ramp_out, ease_out)] {
+            ui.add(
+                egui::DragValue::new(ramp)
+                    .clamp_range(0.01f32..=0.99f32)
+                    .prefix(format!("{} ", label))
+                    .speed(0.01)
+                    .fixed_decimals(2),
+            );
+            egui::ComboBox::from_id_source((id, label))
+                .selected_text(easing.name())
+                .show_ui(ui, |ui| {
+                    for variant in Easing::ALL {
+                        ui.selectable_value(easing, variant, variant.name());
+                    }
+                });
+        }
+    }
+}
+
+fn keyframes_ui(ui: &mut egui::Ui, keyframes: &mut Keyframes) {
+    ui.vertical(|ui| {
+        let mut edit = None;
+        let mut remove = None;
+        let last = keyframes.keys().len() - 1;
+        for (idx, key) in keyframes.keys().iter().enumerate() {
+            let mut key = *key;
+            ui.horizontal(|ui| {
+                // the first key is pinned to the incoming level and the last to t = 1
+                ui.add_enabled_ui(idx != 0 && idx != last, |ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut key.time)
+                            .clamp_range(0f32..=1f32)
+                            .prefix("t ")
+                            .speed(0.01)
+                            .fixed_decimals(2),
+                    );
+                });
+                ui.add_enabled_ui(idx != 0, |ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut key.value)
+                            .prefix("value ")
+                            .speed(0.01)
+                            .fixed_decimals(2),
+                    );
+                });
+                ui.add(
+                    egui::DragValue::new(&mut key.slope)
+                        .prefix("slope ")
+                        .speed(0.01)
+                        .fixed_decimals(2),
+                );
+                if ui
+                    .add_enabled(idx != 0 && idx != last, egui::Button::new("Remove"))
+                    .clicked()
+                {
+                    remove = Some(idx);
+                }
+            });
+            if key != keyframes.keys()[idx] {
+                edit = Some((idx, key));
+            }
+        }
+        if let Some((idx, key)) = edit {
+            keyframes.set(idx, key);
+        }
+        if let Some(idx) = remove {
+            keyframes.remove(idx);
+        }
+        ui.horizontal(|ui| {
+            if ui.button("Add Key").clicked() {
+                // in the middle of the widest gap, on the curve so the shape is unchanged
+                let keys = keyframes.keys();
+                let (a, b) = keys
+                    .windows(2)
+                    .map(|k| (k[0].time, k[1].time))
+                    .max_by(|a, b| (a.1 - a.0).total_cmp(&(b.1 - b.0)))
+                    .unwrap_or((0., 1.));
+                keyframes.insert(keyframes.key_at((a + b) / 2.));
+            }
+            if ui.button("Auto Slopes").clicked() {
+                keyframes.auto_slopes();
+            }
+        });
+    });
+}
+
+fn section_info_ui(ui: &mut egui::Ui, info: &mut SectionInfo) {
+    ui.horizontal(|ui| {
+        ui.label("Name");
+        let mut name = info.name.clone().unwrap_or_default();
+        if ui.text_edit_singleline(&mut name).changed() {
+            info.name = (!name.is_empty()).then_some(name);
+        }
+        egui::ComboBox::from_id_source("section_tag")
+            .selected_text(info.tag.map_or("No Tag", |tag| tag.name()))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut info.tag, None, "No Tag");
+                for tag in ElementTag::ALL {
+                    ui.selectable_value(&mut info.tag, Some(tag), tag.name());
+                }
+            });
+        let mut coloured = info.colour.is_some();
+        ui.checkbox(&mut coloured, "Colour");
+        if coloured && info.colour.is_none() {
+            info.colour = Some([255, 128, 0]);
+        }
+        if !coloured {
+            info.colour = None;
+        }
+        if let Some(colour) = &mut info.colour {
+            ui.color_edit_button_srgb(colour);
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label("Notes");
+        ui.text_edit_singleline(&mut info.notes);
+    });
+}
+
+// rounded to 0.1 and clamped only when the user edits it, values from splits, templates and
+// fits are kept exactly while they are on screen
+fn rounded_drag_value(
+    ui: &mut egui::Ui,
+    value: &mut f32,
+    range: RangeInclusive<f32>,
+    speed: f32,
+    suffix: &str,
+) -> egui::Response {
+    let mut edited = *value;
+    let response = ui.add(
+        egui::DragValue::new(&mut edited)
+            .suffix(suffix)
+            .speed(speed)
+            .fixed_decimals(1),
+    );
+    if response.changed() {
+        *value = ((edited * 10.).round() / 10.).clamp(*range.start(), *range.end());
+    }
+    response
+}
+
+fn binding_ui(ui: &mut egui::Ui, id: &str, binding: &mut Option<String>, parameters: &Parameters) {
+    egui::ComboBox::from_id_source(id)
+        .selected_text(binding.as_deref().unwrap_or("Unbound"))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(binding, None, "Unbound");
+            for name in parameters.keys() {
+                ui.selectable_value(binding, Some(name.clone()), name);
+            }
+        });
+}
+
+fn change_mode_ui(ui: &mut egui::Ui, transition: &mut Transition, incoming: f32) {
+    let mut absolute = transition.mode == ChangeMode::Absolute;
+    if ui.toggle_value(&mut absolute, "Absolute").changed() {
+        transition.set_mode(
+            if absolute {
+                ChangeMode::Absolute
+            } else {
+                ChangeMode::Relative
+            },
+            incoming,
+        );
+    }
+}
+
+fn transition_timing_ui(ui: &mut egui::Ui, transition: &mut Transition, section_length: f32) {
+    ui.horizontal(|ui| {
+        ui.label("Start");
+        ui.add(
+            egui::DragValue::new(&mut transition.offset)
+                .clamp_range(0f32..=section_length)
+                .suffix("s")
+                .speed(0.01)
+                .fixed_decimals(2),
+        );
+        let mut custom_duration = transition.duration.is_some();
+        ui.checkbox(&mut custom_duration, "Duration");
+        if custom_duration && transition.duration.is_none() {
+            transition.duration = Some(section_length - transition.offset);
+        }
+        if !custom_duration {
+            transition.duration = None;
+        }
+        if let Some(duration) = &mut transition.duration {
+            ui.add(
+                egui::DragValue::new(duration)
+                    .clamp_range(0.01f32..=(section_length - transition.offset).max(0.01))
+                    .suffix("s")
+                    .speed(0.01)
+                    .fixed_decimals(2),
+            );
+        }
+    });
+}
+
+// everything the solved track depends on, to tell when it has to be solved again
+fn track_inputs(
+    project: &Project,
+) -> (
+    Vec<FullTransition>,
+    ForceLevels,
+    StartState,
+    SimulationSettings,
+) {
+    (
+        project.transitions.sections().to_vec(),
+        project.transitions.start(),
+        project.start,
+        project.settings,
+    )
+}
+
 fn three_d_angle(radians: f32) -> three_d::Radians {
     three_d::Rad(radians)
 }
@@ -446,7 +1432,7 @@ fn three_d_mat4(mat: Mat4) -> three_d::Mat4 {
     )
 }
 
-fn three_d_image(context: &three_d::Context, path: &Path) -> CpuTexture {
+fn three_d_image(_context: &three_d::Context, path: &Path) -> CpuTexture {
     let image = image::open(path).unwrap().to_rgba8();
     let data = image.pixels().map(|v| v.0).collect::<Vec<_>>();
 